@@ -0,0 +1,183 @@
+//! Compares the bit-packed snapshot and input encodings with the byte-aligned encoder they
+//! replaced, kept here as it was. Run with `cargo bench --bench bandwidth`.
+
+use std::{hint::black_box, time::Instant};
+
+use server_udp::{
+    config::globals,
+    config::globals::commands::{PLAYER_INPUT, ROOM_SNAPSHOT},
+    game::{
+        Position,
+        input::{InputAction, InputCommand},
+        player::{LifeState, PlayerID},
+        snapshot::{EnemySnapshot, PlayerSnapshot, RoomSnapshot},
+    },
+    network::message::Message,
+};
+
+const ITERATIONS: u32 = 100_000;
+
+/// The byte-aligned ROOM_SNAPSHOT, every field at its full width
+fn legacy_snapshot(snapshot: &RoomSnapshot) -> Vec<u8> {
+    let mut packet = vec![ROOM_SNAPSHOT];
+    packet.extend_from_slice(&snapshot.tick.to_le_bytes());
+    packet.extend_from_slice(&snapshot.ack_input_seq.to_le_bytes());
+    packet.extend_from_slice(&snapshot.ack_position.x.to_le_bytes());
+    packet.extend_from_slice(&snapshot.ack_position.y.to_le_bytes());
+
+    packet.push(snapshot.players.len() as u8);
+    for player in snapshot.players.iter() {
+        packet.extend_from_slice(&player.id.to_le_bytes());
+        packet.extend_from_slice(&player.position.x.to_le_bytes());
+        packet.extend_from_slice(&player.position.y.to_le_bytes());
+        packet.extend_from_slice(&player.velocity.x.to_le_bytes());
+        packet.extend_from_slice(&player.velocity.y.to_le_bytes());
+        packet.extend_from_slice(&player.health.to_le_bytes());
+        packet.extend_from_slice(&player.ping_ms.to_le_bytes());
+        packet.push(player.life.as_u8());
+
+        // Downed players also carry their timers
+        if let LifeState::Downed {
+            bleed_out,
+            revive_progress,
+        } = player.life
+        {
+            packet.extend_from_slice(&bleed_out.to_le_bytes());
+            packet.extend_from_slice(&revive_progress.to_le_bytes());
+        }
+    }
+
+    packet.extend_from_slice(&(snapshot.enemies.len() as u16).to_le_bytes());
+    for enemy in snapshot.enemies.iter() {
+        packet.extend_from_slice(&enemy.id.to_le_bytes());
+        packet.extend_from_slice(&enemy.position.x.to_le_bytes());
+        packet.extend_from_slice(&enemy.position.y.to_le_bytes());
+        packet.extend_from_slice(&enemy.health.to_le_bytes());
+    }
+
+    packet
+}
+
+/// The byte-aligned PLAYER_INPUT, a full sequence number and two floats a command
+fn legacy_inputs(player_id: PlayerID, commands: &[InputCommand]) -> Vec<u8> {
+    let mut packet = vec![PLAYER_INPUT];
+    packet.extend_from_slice(&player_id.to_le_bytes());
+    packet.push(commands.len() as u8);
+
+    for command in commands.iter() {
+        let (kind, x, y) = match command.action {
+            InputAction::Move(x, y) => (0u8, x, y),
+            InputAction::Shoot(x, y) => (1u8, x, y),
+        };
+        packet.extend_from_slice(&command.seq.to_le_bytes());
+        packet.push(kind);
+        packet.extend_from_slice(&x.to_le_bytes());
+        packet.extend_from_slice(&y.to_le_bytes());
+    }
+
+    packet
+}
+
+fn sample_snapshot(players: u32, enemies: u32) -> RoomSnapshot {
+    RoomSnapshot {
+        tick: 18_000,
+        ack_input_seq: 5_400,
+        ack_position: Position::new(412.5, 233.25),
+        players: (0..players)
+            .map(|i| PlayerSnapshot {
+                id: i + 1,
+                position: Position::new(400.0 + i as f32 * 20.0, 230.0),
+                velocity: Position::new(84.8, -84.8),
+                health: 100 - i as i32 * 15,
+                ping_ms: 40 + i as u16 * 12,
+                life: if i == 1 {
+                    LifeState::Downed {
+                        bleed_out: 22.5,
+                        revive_progress: 0.75,
+                    }
+                } else {
+                    LifeState::Alive
+                },
+            })
+            .collect(),
+        enemies: (0..enemies)
+            .map(|i| EnemySnapshot {
+                id: 1_000 + i,
+                position: Position::new(300.0 + i as f32 * 9.5, 180.0 + i as f32 * 3.0),
+                health: 30,
+            })
+            .collect(),
+    }
+}
+
+fn sample_inputs(count: u32) -> Vec<InputCommand> {
+    (0..count)
+        .map(|i| InputCommand {
+            seq: 5_400 + i,
+            action: if i % 4 == 3 {
+                InputAction::Shoot(-0.6, 0.8)
+            } else {
+                InputAction::Move(0.6, -0.8)
+            },
+        })
+        .collect()
+}
+
+fn report(name: &str, message: Message, legacy: impl Fn(&Message) -> Vec<u8>) {
+    let start = Instant::now();
+    let mut legacy_packet = Vec::new();
+    for _ in 0..ITERATIONS {
+        legacy_packet = legacy(black_box(&message));
+    }
+    let legacy_encode = start.elapsed() / ITERATIONS;
+    let legacy = legacy_packet.len();
+
+    let start = Instant::now();
+    let mut packet = Vec::new();
+    for _ in 0..ITERATIONS {
+        packet = black_box(&message).serialize();
+    }
+    let encode = start.elapsed() / ITERATIONS;
+
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        black_box(Message::deserialize(black_box(&packet)).expect("sample should decode"));
+    }
+    let decode = start.elapsed() / ITERATIONS;
+
+    let packed = packet.len();
+    let per_second = packed as u64 * globals::TICK_RATE_HZ;
+    println!(
+        "{name:<28} legacy {legacy:>5} B  packed {packed:>5} B  saved {:>5.1}%  \
+         {:>6.1} KiB/s at {} Hz  encode {encode:?} (legacy {legacy_encode:?})  decode {decode:?}",
+        100.0 * (1.0 - packed as f64 / legacy as f64),
+        per_second as f64 / 1024.0,
+        globals::TICK_RATE_HZ,
+    );
+}
+
+fn main() {
+    for (players, enemies) in [(1, 0), (4, 12), (4, 64)] {
+        let snapshot = sample_snapshot(players, enemies);
+        report(
+            &format!("snapshot {players}p/{enemies}e"),
+            Message::RoomSnapshot(snapshot),
+            |message| match message {
+                Message::RoomSnapshot(snapshot) => legacy_snapshot(snapshot),
+                _ => unreachable!(),
+            },
+        );
+    }
+
+    for count in [1, 8, globals::MAX_INPUTS_PER_PACKET as u32] {
+        let commands = sample_inputs(count);
+        report(
+            &format!("input x{count}"),
+            Message::PlayerInput(7, commands),
+            |message| match message {
+                Message::PlayerInput(player_id, commands) => legacy_inputs(*player_id, commands),
+                _ => unreachable!(),
+            },
+        );
+    }
+}
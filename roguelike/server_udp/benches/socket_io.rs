@@ -0,0 +1,134 @@
+//! Compares one syscall per datagram with the batched recvmmsg/sendmmsg path over
+//! loopback. Run with `cargo bench --bench socket_io --features mmsg` on Linux, without the
+//! feature both columns take the portable path.
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use server_udp::{
+    config::globals,
+    network::socket::{self, RecvBatch},
+};
+use tokio::net::UdpSocket;
+
+/// A compressed snapshot is about this big
+const PACKET_SIZE: usize = 200;
+const ROUNDS: u32 = 2_000;
+
+async fn bind() -> UdpSocket {
+    UdpSocket::bind("127.0.0.1:0")
+        .await
+        .expect("loopback should bind")
+}
+
+/// Each round sends one packet to every client, like a tick's snapshots
+async fn bench_send(clients: usize) {
+    let server = bind().await;
+    let mut receivers = Vec::with_capacity(clients);
+    for _ in 0..clients {
+        receivers.push(bind().await);
+    }
+
+    let packets: Vec<(Vec<u8>, SocketAddr)> = receivers
+        .iter()
+        .map(|receiver| {
+            let addr = receiver.local_addr().expect("bound socket has an address");
+            (vec![0xAB; PACKET_SIZE], addr)
+        })
+        .collect();
+
+    let mut times = [Duration::ZERO; 2];
+    for round in 0..ROUNDS {
+        // Alternate so neither path always runs against a warmer cache
+        for pass in 0..2 {
+            let path = (round as usize + pass) % 2;
+
+            let start = Instant::now();
+            let failed = if path == 0 {
+                socket::send_each(&server, &packets).await
+            } else {
+                socket::send_many(&server, &packets).await
+            };
+            times[path] += start.elapsed();
+            assert!(failed.is_empty(), "loopback send failed: {:?}", failed);
+
+            // Nobody reads in between, so keep the receive queues from filling up
+            let mut buf = [0u8; PACKET_SIZE];
+            for receiver in receivers.iter() {
+                while receiver.try_recv_from(&mut buf).is_ok() {}
+            }
+        }
+    }
+
+    report(&format!("send to {clients} clients"), times, clients);
+}
+
+/// Each round a client burst lands on the server socket and is read back out
+async fn bench_recv(burst: usize) {
+    let server = bind().await;
+    let server_addr = server.local_addr().expect("bound socket has an address");
+    let client = bind().await;
+    let packets = vec![(vec![0xCD; PACKET_SIZE], server_addr); burst];
+
+    let mut batch = RecvBatch::new(globals::RECV_BATCH_SIZE, globals::MAX_DATAGRAM_SIZE);
+    let mut times = [Duration::ZERO; 2];
+
+    for round in 0..ROUNDS {
+        for pass in 0..2 {
+            let path = (round as usize + pass) % 2;
+            socket::send_each(&client, &packets).await;
+
+            let start = Instant::now();
+            let mut read = 0;
+            while read < burst {
+                if path == 0 {
+                    batch.recv_each(&server).await
+                } else {
+                    batch.recv(&server).await
+                }
+                .expect("loopback recv failed");
+                read += batch.iter().count();
+            }
+            times[path] += start.elapsed();
+        }
+    }
+
+    report(&format!("recv burst of {burst}"), times, burst);
+}
+
+fn report(name: &str, times: [Duration; 2], datagrams: usize) {
+    let per_datagram = |time: Duration| time / (ROUNDS * datagrams as u32);
+    println!(
+        "{name:<24} each {:>10?}/datagram  batched {:>10?}/datagram  speedup {:.2}x",
+        per_datagram(times[0]),
+        per_datagram(times[1]),
+        times[0].as_secs_f64() / times[1].as_secs_f64(),
+    );
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("runtime should start");
+
+    println!(
+        "batched path: {}",
+        if socket::BATCHED {
+            "recvmmsg/sendmmsg"
+        } else {
+            "portable fallback"
+        }
+    );
+
+    runtime.block_on(async {
+        for clients in [16, 128, 512] {
+            bench_send(clients).await;
+        }
+        for burst in [8, 32, 128] {
+            bench_recv(burst).await;
+        }
+    });
+}
@@ -0,0 +1,16 @@
+#![no_main]
+
+// cargo +nightly fuzz run decompress fuzz/corpus/decompress
+
+use libfuzzer_sys::fuzz_target;
+use server_udp::{config::settings::CompressionSettings, network::compress::Compressor};
+
+// Whatever a client claims in the header, decompressing must fail cleanly or give back
+// exactly the length it claimed
+fuzz_target!(|packet: &[u8]| {
+    let compressor = Compressor::new(CompressionSettings::default()).unwrap();
+
+    if let Ok(message) = compressor.decompress(packet) {
+        assert_eq!(message.len(), u16::from_le_bytes([packet[2], packet[3]]) as usize);
+    }
+});
@@ -0,0 +1,18 @@
+#![no_main]
+
+// cargo +nightly fuzz run deserialize fuzz/corpus/deserialize
+
+use libfuzzer_sys::fuzz_target;
+use server_udp::network::message::Message;
+
+// Any packet either fails to decode or decodes to a message that encodes
+// back to itself. Panics are the bug either way.
+fuzz_target!(|packet: &[u8]| {
+    let Ok(message) = Message::deserialize(packet) else {
+        return;
+    };
+
+    let encoded = message.serialize();
+    let decoded = Message::deserialize(&encoded).expect("re-encoded message must decode");
+    assert_eq!(decoded.serialize(), encoded, "encoding is not stable");
+});
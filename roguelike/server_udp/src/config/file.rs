@@ -0,0 +1,295 @@
+use std::{
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use super::settings::{LogFormat, ServerSettings};
+use crate::game::mode::DeathRules;
+
+/// `ROGUELIKE_NETWORK_PORT=5000` sets `port` in `[network]`
+pub const ENV_PREFIX: &str = "ROGUELIKE_";
+
+/// Settings read from a TOML file or the environment. Anything left out keeps the value
+/// it had before, so layers can be applied one over the other.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub network: NetworkConfig,
+    pub timing: TimingConfig,
+    pub rooms: RoomConfig,
+    pub security: SecurityConfig,
+    pub gameplay: GameplayConfig,
+    pub server: ServerConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub bind: Option<Vec<IpAddr>>,
+    pub port: Option<u16>,
+    pub sockets: Option<usize>,
+    pub worker_threads: Option<usize>,
+    pub receive_workers: Option<usize>,
+    pub receive_queue_depth: Option<usize>,
+    pub mtu: Option<usize>,
+    pub compression: Option<bool>,
+    pub compression_threshold: Option<usize>,
+    pub zstd_level: Option<i32>,
+    pub compression_dictionary: Option<PathBuf>,
+    pub bandwidth_budget: Option<u32>,
+    pub min_bandwidth: Option<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimingConfig {
+    pub ping_interval_ms: Option<u64>,
+    pub scan_interval_secs: Option<u64>,
+    pub inactivity_timeout_secs: Option<u64>,
+    pub shutdown_grace_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomConfig {
+    pub max_rooms: Option<usize>,
+    pub max_players: Option<usize>,
+}
+
+/// Limits on what a client can make the server hold on to
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    pub fragment_timeout_ms: Option<u64>,
+    pub max_message_size: Option<usize>,
+    pub max_pending_bytes_per_client: Option<usize>,
+    pub max_pending_bytes: Option<usize>,
+    pub banned: Option<Vec<IpAddr>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GameplayConfig {
+    pub view_radius: Option<f32>,
+    pub line_of_sight: Option<bool>,
+    pub enemy_count_scale: Option<f32>,
+    pub enemy_health_scale: Option<f32>,
+    pub enemy_speed_scale: Option<f32>,
+    pub enemy_damage_scale: Option<f32>,
+    pub max_rewind_ms: Option<u64>,
+    pub interp_delay_ms: Option<u64>,
+    pub modes: ModesConfig,
+}
+
+/// `[gameplay.modes.standard]` and so on
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModesConfig {
+    pub standard: DeathRulesConfig,
+    pub hardcore: DeathRulesConfig,
+    pub casual: DeathRulesConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeathRulesConfig {
+    pub downed: Option<bool>,
+    pub bleed_out_secs: Option<f32>,
+    pub revive_radius: Option<f32>,
+    pub revive_secs: Option<f32>,
+    pub revive_health: Option<i32>,
+}
+
+impl DeathRulesConfig {
+    fn apply(&self, rules: &mut DeathRules) {
+        set(&mut rules.downed_enabled, self.downed);
+        set(&mut rules.bleed_out_secs, self.bleed_out_secs);
+        set(&mut rules.revive_radius, self.revive_radius);
+        set(&mut rules.revive_secs, self.revive_secs);
+        set(&mut rules.revive_health, self.revive_health);
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub motd: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub filter: Option<String>,
+    pub format: Option<LogFormat>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Can not read config {}: {e}", path.display()))?;
+
+        toml::from_str(&text).map_err(|e| format!("Invalid config {}: {e}", path.display()))
+    }
+
+    /// Every `ROGUELIKE_<SECTION>_<KEY>` variable. Values are read as TOML when they
+    /// parse as one (numbers, booleans, `["::1"]`) and as plain strings otherwise.
+    pub fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut config = Table::new();
+
+        for (name, raw) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_lowercase();
+            let Some((section, field)) = key.split_once('_') else {
+                return Err(format!("{name} does not name a [section] and key"));
+            };
+
+            let value = toml::from_str::<Table>(&format!("value = {raw}"))
+                .ok()
+                .and_then(|mut table| table.remove("value"))
+                .unwrap_or_else(|| Value::String(raw.clone()));
+
+            // Checked on its own so the error can name the variable
+            let mut single = Table::new();
+            single.insert(field.to_string(), value.clone());
+            let mut wrapped = Table::new();
+            wrapped.insert(section.to_string(), Value::Table(single));
+            Value::Table(wrapped)
+                .try_into::<ConfigFile>()
+                .map_err(|e| format!("Invalid {name}: {e}"))?;
+
+            if let Value::Table(table) = config
+                .entry(section.to_string())
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                table.insert(field.to_string(), value);
+            }
+        }
+
+        Value::Table(config)
+            .try_into()
+            .map_err(|e| format!("Invalid environment: {e}"))
+    }
+
+    /// Overwrite whatever this layer sets
+    pub fn apply(&self, settings: &mut ServerSettings) {
+        let network = &self.network;
+        set(&mut settings.bind, network.bind.clone());
+        set(&mut settings.port, network.port);
+        set(&mut settings.sockets, network.sockets);
+        set(&mut settings.worker_threads, network.worker_threads);
+        set(&mut settings.receive_workers, network.receive_workers);
+        set(
+            &mut settings.receive_queue_depth,
+            network.receive_queue_depth,
+        );
+        set(&mut settings.fragment.mtu, network.mtu);
+        set(&mut settings.compression.enabled, network.compression);
+        set(
+            &mut settings.compression.threshold,
+            network.compression_threshold,
+        );
+        set(&mut settings.compression.zstd_level, network.zstd_level);
+        set(
+            &mut settings.compression.dictionary,
+            network.compression_dictionary.clone().map(Some),
+        );
+        set(&mut settings.bandwidth.budget, network.bandwidth_budget);
+        set(&mut settings.bandwidth.min_rate, network.min_bandwidth);
+
+        let timing = &self.timing;
+        set(
+            &mut settings.liveness.ping_interval,
+            timing.ping_interval_ms.map(Duration::from_millis),
+        );
+        set(
+            &mut settings.liveness.scan_interval,
+            timing.scan_interval_secs.map(Duration::from_secs),
+        );
+        set(
+            &mut settings.liveness.inactivity_timeout,
+            timing.inactivity_timeout_secs.map(Duration::from_secs),
+        );
+        set(
+            &mut settings.shutdown_grace,
+            timing.shutdown_grace_secs.map(Duration::from_secs),
+        );
+
+        set(&mut settings.rooms.max_rooms, self.rooms.max_rooms);
+        set(&mut settings.rooms.max_players, self.rooms.max_players);
+
+        let security = &self.security;
+        set(
+            &mut settings.fragment.timeout,
+            security.fragment_timeout_ms.map(Duration::from_millis),
+        );
+        set(
+            &mut settings.fragment.max_message_size,
+            security.max_message_size,
+        );
+        set(
+            &mut settings.fragment.max_pending_bytes_per_client,
+            security.max_pending_bytes_per_client,
+        );
+        set(
+            &mut settings.fragment.max_pending_bytes,
+            security.max_pending_bytes,
+        );
+        set(&mut settings.banned, security.banned.clone());
+
+        set(
+            &mut settings.interest.view_radius,
+            self.gameplay.view_radius,
+        );
+        set(
+            &mut settings.interest.line_of_sight,
+            self.gameplay.line_of_sight,
+        );
+        let gameplay = &self.gameplay;
+        set(
+            &mut settings.balance.enemy_count,
+            gameplay.enemy_count_scale,
+        );
+        set(
+            &mut settings.balance.enemy_health,
+            gameplay.enemy_health_scale,
+        );
+        set(
+            &mut settings.balance.enemy_speed,
+            gameplay.enemy_speed_scale,
+        );
+        set(
+            &mut settings.balance.enemy_damage,
+            gameplay.enemy_damage_scale,
+        );
+        set(
+            &mut settings.lag.max_rewind,
+            gameplay.max_rewind_ms.map(Duration::from_millis),
+        );
+        set(
+            &mut settings.lag.interp_delay,
+            gameplay.interp_delay_ms.map(Duration::from_millis),
+        );
+        gameplay.modes.standard.apply(&mut settings.modes.standard);
+        gameplay.modes.hardcore.apply(&mut settings.modes.hardcore);
+        gameplay.modes.casual.apply(&mut settings.modes.casual);
+
+        set(&mut settings.motd, self.server.motd.clone());
+
+        set(&mut settings.logging.filter, self.logging.filter.clone());
+        set(&mut settings.logging.format, self.logging.format);
+    }
+}
+
+fn set<T>(setting: &mut T, value: Option<T>) {
+    if let Some(value) = value {
+        *setting = value;
+    }
+}
@@ -9,8 +9,107 @@ pub mod commands {
     pub const PLAYER_INPUT: u8 = 7;
     pub const ROOM_SNAPSHOT: u8 = 8;
     pub const ERROR: u8 = 9;
+    pub const USE_ITEM: u8 = 10;
+    pub const DROP_ITEM: u8 = 11;
+    pub const EQUIP: u8 = 12;
+    pub const INTERACT: u8 = 13;
+    pub const INVENTORY: u8 = 14;
+    pub const ENTITY_SPAWN: u8 = 15;
+    pub const ENTITY_DESPAWN: u8 = 16;
+    pub const FLOOR_CHANGED: u8 = 17;
+    pub const VOTE_DESCEND: u8 = 18;
+    pub const PLAYER_STATE: u8 = 19;
+    pub const RUN_ENDED: u8 = 20;
+    pub const ACTOR_SPAWN: u8 = 21;
+    pub const ACTOR_DESPAWN: u8 = 22;
+    pub const PONG: u8 = 23;
+    pub const FRAGMENT: u8 = 24;
+    pub const BATCH: u8 = 25;
+    pub const COMPRESSED: u8 = 26;
+    pub const SNAPSHOT_ACK: u8 = 27;
+    pub const SERVER_SHUTDOWN: u8 = 28;
+    pub const MOTD: u8 = 29;
 }
 
 pub const DEFAULT_PORT: u16 = 5678;
+/// Everything at info and above, packet traces off
+pub const LOG_FILTER: &str = "info";
+/// Threads in the tokio runtime
+pub const WORKER_THREADS: usize = 6;
 pub const CONNECTION_TIMEOUT_SEC: std::time::Duration = std::time::Duration::from_secs(5);
-pub const PING_INTERVAL_MS: std::time::Duration = std::time::Duration::from_secs(15);
+pub const TICK_RATE_HZ: u64 = 30;
+/// How long running rooms get to finish once the server is asked to stop
+pub const SHUTDOWN_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(10);
+/// Clients are reminded of the countdown this often during the grace period
+pub const SHUTDOWN_NOTICE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// How often the config file is checked for changes
+pub const CONFIG_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// Tasks still running this long after being cancelled are left behind
+pub const TASK_STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Largest payload a UDP datagram can carry
+pub const MAX_DATAGRAM_SIZE: usize = 65_507;
+/// Packets above this get fragmented. Leaves room for IP/UDP headers and tunnels.
+pub const DEFAULT_MTU: usize = 1200;
+/// Half-assembled messages are dropped after this long
+pub const FRAGMENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// How often each receive worker throws away the fragment sets that timed out
+pub const FRAGMENT_EXPIRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+pub const MAX_FRAGMENTED_MESSAGE_SIZE: usize = 64 * 1024;
+pub const MAX_REASSEMBLY_BYTES_PER_CLIENT: usize = 256 * 1024;
+pub const MAX_REASSEMBLY_BYTES: usize = 16 * 1024 * 1024;
+/// Messages held per client between flushes before new ones are dropped
+pub const MAX_QUEUED_MESSAGES: usize = 256;
+/// Workers handling received datagrams. A client always lands on the same one, so its
+/// packets are handled in the order they arrived.
+pub const RECEIVE_WORKERS: usize = 4;
+/// Datagrams waiting per worker before new ones are dropped
+pub const RECEIVE_QUEUE_DEPTH: usize = 1024;
+/// Receive buffers kept around for reuse
+pub const RECEIVE_BUFFER_POOL: usize = 256;
+pub const RECEIVE_STATS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// Datagrams read per receive call
+pub const RECV_BATCH_SIZE: usize = 32;
+/// Most datagrams handed to one sendmmsg call
+pub const SEND_BATCH_SIZE: usize = 64;
+/// Messages smaller than this are never worth compressing
+pub const COMPRESSION_THRESHOLD: usize = 128;
+pub const ZSTD_LEVEL: i32 = 3;
+/// Most a client is sent per second while nothing gets lost
+pub const BANDWIDTH_BUDGET: u32 = 64 * 1024;
+/// Loss never pushes a client's send rate below this
+pub const MIN_BANDWIDTH: u32 = 4 * 1024;
+
+/// How far behind the server clients render other entities, unless configured otherwise
+pub const CLIENT_INTERP_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+/// Most inputs a client may repeat in one PLAYER_INPUT packet
+pub const MAX_INPUTS_PER_PACKET: usize = 16;
+/// Inputs held per player waiting for their tick
+pub const MAX_BUFFERED_INPUTS: usize = 64;
+/// Inputs further than this ahead of the last applied one are refused, so a bogus sequence
+/// number can not make the server skip over billions of inputs
+pub const MAX_INPUT_LEAD: u32 = 256;
+/// Ticks to wait for a missing input before skipping it
+pub const INPUT_GAP_TOLERANCE_TICKS: u32 = 2;
+/// With more inputs than this queued, apply extra inputs per tick to catch up
+pub const INPUT_CATCH_UP_THRESHOLD: usize = 4;
+
+/// Rooms open at once, further CREATE_ROOMs are refused
+pub const MAX_ROOMS: usize = 1024;
+pub const MAX_PLAYERS_PER_ROOM: usize = 8;
+
+/// Lag compensation never rewinds targets further back than this, unless configured otherwise
+pub const MAX_REWIND: std::time::Duration = std::time::Duration::from_millis(250);
+/// Configured rewinds are capped here, every tick in between is kept for hit tests
+pub const MAX_REWIND_LIMIT: std::time::Duration = std::time::Duration::from_secs(1);
+pub const PING_INTERVAL_MS: std::time::Duration = std::time::Duration::from_secs(2);
+pub const INACTIVITY_SCAN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+pub const INACTIVITY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// Sent pings remembered for matching pongs, older ones count as lost
+pub const MAX_PENDING_PINGS: usize = 8;
+
+/// How close a player has to be to pick up an item or open a chest
+pub const INTERACT_RADIUS: f32 = 48.0;
+
+/// How close a player has to be to the stairs to count as ready to descend
+pub const STAIRS_RADIUS: f32 = 24.0;
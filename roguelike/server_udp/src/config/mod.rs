@@ -1 +1,3 @@
+pub mod file;
 pub mod globals;
+pub mod settings;
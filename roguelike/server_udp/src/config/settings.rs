@@ -0,0 +1,518 @@
+use std::{
+    fmt::{self, Debug, Display},
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use super::globals;
+use crate::{
+    game::{
+        floor::Balance, history::LagCompensation, interest::InterestSettings, mode::ModeSettings,
+        player::PLAYER_MAX_HEALTH,
+    },
+    logging,
+};
+
+/// How the server decides a client is gone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LivenessSettings {
+    /// How often every client gets a PING
+    pub ping_interval: Duration,
+    /// How often the server scans for silent clients
+    pub scan_interval: Duration,
+    /// Clients that send nothing valid for this long are dropped
+    pub inactivity_timeout: Duration,
+}
+
+impl Default for LivenessSettings {
+    fn default() -> Self {
+        LivenessSettings {
+            ping_interval: globals::PING_INTERVAL_MS,
+            scan_interval: globals::INACTIVITY_SCAN_INTERVAL,
+            inactivity_timeout: globals::INACTIVITY_TIMEOUT,
+        }
+    }
+}
+
+impl LivenessSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.ping_interval.is_zero() || self.scan_interval.is_zero() {
+            return Err(
+                "timing.ping_interval_ms and timing.scan_interval_secs must be above zero"
+                    .to_string(),
+            );
+        }
+
+        // A client needs a few pings to answer before we give up on it
+        if self.inactivity_timeout < self.ping_interval * 2 {
+            return Err(format!(
+                "timing.inactivity_timeout_secs ({:?}) must be at least twice timing.ping_interval_ms ({:?})",
+                self.inactivity_timeout, self.ping_interval
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits for splitting big messages and putting them back together
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FragmentSettings {
+    /// Largest datagram we send, bigger messages go out as fragments
+    pub mtu: usize,
+    pub timeout: Duration,
+    pub max_message_size: usize,
+    pub max_pending_bytes_per_client: usize,
+    pub max_pending_bytes: usize,
+}
+
+impl Default for FragmentSettings {
+    fn default() -> Self {
+        FragmentSettings {
+            mtu: globals::DEFAULT_MTU,
+            timeout: globals::FRAGMENT_TIMEOUT,
+            max_message_size: globals::MAX_FRAGMENTED_MESSAGE_SIZE,
+            max_pending_bytes_per_client: globals::MAX_REASSEMBLY_BYTES_PER_CLIENT,
+            max_pending_bytes: globals::MAX_REASSEMBLY_BYTES,
+        }
+    }
+}
+
+impl FragmentSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.mtu < 64 || self.mtu > globals::MAX_DATAGRAM_SIZE {
+            return Err(format!(
+                "network.mtu ({}) must be between 64 and {}",
+                self.mtu,
+                globals::MAX_DATAGRAM_SIZE
+            ));
+        }
+
+        if self.timeout.is_zero() {
+            return Err("security.fragment_timeout_ms must be above zero".to_string());
+        }
+
+        if self.max_message_size > self.max_pending_bytes_per_client
+            || self.max_pending_bytes_per_client > self.max_pending_bytes
+        {
+            return Err(format!(
+                "security.max_message_size ({}) must be at most security.max_pending_bytes_per_client ({}), which must be at most security.max_pending_bytes ({})",
+                self.max_message_size, self.max_pending_bytes_per_client, self.max_pending_bytes
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Which clients get compressed messages is decided in the handshake, these only say
+/// what the server is willing to do
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompressionSettings {
+    pub enabled: bool,
+    /// Messages below this many bytes go out as they are
+    pub threshold: usize,
+    pub zstd_level: i32,
+    /// Zstd dictionary shared with clients ahead of time
+    pub dictionary: Option<PathBuf>,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        CompressionSettings {
+            enabled: true,
+            threshold: globals::COMPRESSION_THRESHOLD,
+            zstd_level: globals::ZSTD_LEVEL,
+            dictionary: None,
+        }
+    }
+}
+
+impl CompressionSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=22).contains(&self.zstd_level) {
+            return Err(format!(
+                "network.zstd_level ({}) must be between 1 and 22",
+                self.zstd_level
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Per-client send rate limits, in bytes per second
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandwidthSettings {
+    pub budget: u32,
+    pub min_rate: u32,
+}
+
+impl Default for BandwidthSettings {
+    fn default() -> Self {
+        BandwidthSettings {
+            budget: globals::BANDWIDTH_BUDGET,
+            min_rate: globals::MIN_BANDWIDTH,
+        }
+    }
+}
+
+impl BandwidthSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_rate == 0 || self.min_rate > self.budget {
+            return Err(format!(
+                "network.bandwidth_budget ({}) must be at least network.min_bandwidth ({}), which must be above zero",
+                self.budget, self.min_rate
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// How much a single server takes on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoomLimits {
+    pub max_rooms: usize,
+    pub max_players: usize,
+}
+
+impl Default for RoomLimits {
+    fn default() -> Self {
+        RoomLimits {
+            max_rooms: globals::MAX_ROOMS,
+            max_players: globals::MAX_PLAYERS_PER_ROOM,
+        }
+    }
+}
+
+impl RoomLimits {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_rooms == 0 || self.max_players == 0 {
+            return Err("rooms.max_rooms and rooms.max_players must be at least 1".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event, for people
+    Text,
+    /// One JSON object per line, for log shipping
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {s}, expected text or json")),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => f.write_str("text"),
+            LogFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogSettings {
+    /// Levels per module, like `info,server_udp::server::room_actor=debug`
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            filter: globals::LOG_FILTER.to_string(),
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl LogSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        logging::filter(&self.filter).map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSettings {
+    /// Addresses to listen on, all with the same port. `::` alone is dual-stack.
+    pub bind: Vec<IpAddr>,
+    pub port: u16,
+    /// Sockets sharing the port through SO_REUSEPORT, each with its own receive loop
+    pub sockets: usize,
+    pub worker_threads: usize,
+    /// Tasks handling received datagrams, each with a queue this deep
+    pub receive_workers: usize,
+    pub receive_queue_depth: usize,
+    pub liveness: LivenessSettings,
+    pub fragment: FragmentSettings,
+    pub compression: CompressionSettings,
+    pub bandwidth: BandwidthSettings,
+    /// How long running rooms get to finish once the server is asked to stop
+    pub shutdown_grace: Duration,
+    pub rooms: RoomLimits,
+    /// What each client gets to see of its room
+    pub interest: InterestSettings,
+    pub balance: Balance,
+    pub lag: LagCompensation,
+    /// Downed and revive rules of each game mode
+    pub modes: ModeSettings,
+    /// Datagrams from these addresses are ignored
+    pub banned: Vec<IpAddr>,
+    /// Sent to every client after its handshake, empty for none
+    pub motd: String,
+    pub logging: LogSettings,
+}
+
+/// One setting that differs between two sets of settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettingChange {
+    /// Config key, like `network.port`
+    pub key: &'static str,
+    pub old: String,
+    pub new: String,
+    /// Only read when the server starts
+    pub needs_restart: bool,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            bind: vec![IpAddr::from([0, 0, 0, 0])],
+            port: globals::DEFAULT_PORT,
+            sockets: 1,
+            worker_threads: globals::WORKER_THREADS,
+            receive_workers: globals::RECEIVE_WORKERS,
+            receive_queue_depth: globals::RECEIVE_QUEUE_DEPTH,
+            liveness: LivenessSettings::default(),
+            fragment: FragmentSettings::default(),
+            compression: CompressionSettings::default(),
+            bandwidth: BandwidthSettings::default(),
+            shutdown_grace: globals::SHUTDOWN_GRACE_PERIOD,
+            rooms: RoomLimits::default(),
+            interest: InterestSettings::default(),
+            balance: Balance::default(),
+            lag: LagCompensation::default(),
+            modes: ModeSettings::default(),
+            banned: Vec::new(),
+            motd: String::new(),
+            logging: LogSettings::default(),
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bind.is_empty() {
+            return Err("network.bind needs at least one address".to_string());
+        }
+        for (i, ip) in self.bind.iter().enumerate() {
+            if self.bind[..i].contains(ip) {
+                return Err(format!("network.bind lists {ip} more than once"));
+            }
+        }
+        if self.sockets == 0 {
+            return Err("network.sockets must be at least 1".to_string());
+        }
+        if self.sockets > 1 && !cfg!(unix) {
+            return Err(
+                "network.sockets above 1 needs SO_REUSEPORT, which is Unix only".to_string(),
+            );
+        }
+        if self.worker_threads == 0 || self.receive_workers == 0 || self.receive_queue_depth == 0 {
+            return Err(
+                "network.worker_threads, network.receive_workers and network.receive_queue_depth must be at least 1"
+                    .to_string(),
+            );
+        }
+        if !(self.interest.view_radius > 0.0 && self.interest.view_radius.is_finite()) {
+            return Err(format!(
+                "gameplay.view_radius ({}) must be above zero",
+                self.interest.view_radius
+            ));
+        }
+        let balance = self.balance;
+        let scales = [
+            balance.enemy_count,
+            balance.enemy_health,
+            balance.enemy_speed,
+            balance.enemy_damage,
+        ];
+        if !scales.iter().all(|scale| *scale > 0.0 && scale.is_finite()) {
+            return Err(
+                "gameplay.enemy_count_scale, gameplay.enemy_health_scale, gameplay.enemy_speed_scale and gameplay.enemy_damage_scale must be above zero"
+                    .to_string(),
+            );
+        }
+
+        if self.lag.max_rewind > globals::MAX_REWIND_LIMIT
+            || self.lag.interp_delay > globals::MAX_REWIND_LIMIT
+        {
+            return Err(format!(
+                "gameplay.max_rewind_ms ({}) and gameplay.interp_delay_ms ({}) must be at most {}",
+                self.lag.max_rewind.as_millis(),
+                self.lag.interp_delay.as_millis(),
+                globals::MAX_REWIND_LIMIT.as_millis()
+            ));
+        }
+
+        let modes = [
+            ("standard", self.modes.standard),
+            ("hardcore", self.modes.hardcore),
+            ("casual", self.modes.casual),
+        ];
+        for (mode, rules) in modes {
+            // Without a downed state the rest is never read
+            if !rules.downed_enabled {
+                continue;
+            }
+            let valid = [rules.bleed_out_secs, rules.revive_radius]
+                .iter()
+                .all(|value| *value > 0.0 && value.is_finite())
+                && rules.revive_secs >= 0.0
+                && rules.revive_secs.is_finite();
+            if !valid {
+                return Err(format!(
+                    "gameplay.modes.{mode}.bleed_out_secs ({}) and revive_radius ({}) must be above zero and revive_secs ({}) at least zero",
+                    rules.bleed_out_secs, rules.revive_radius, rules.revive_secs
+                ));
+            }
+            if !(1..=PLAYER_MAX_HEALTH).contains(&rules.revive_health) {
+                return Err(format!(
+                    "gameplay.modes.{mode}.revive_health ({}) must be between 1 and {PLAYER_MAX_HEALTH}",
+                    rules.revive_health
+                ));
+            }
+        }
+
+        self.liveness.validate()?;
+        self.fragment.validate()?;
+        self.compression.validate()?;
+        self.bandwidth.validate()?;
+        self.rooms.validate()?;
+        self.logging.validate()
+    }
+
+    /// Every setting that `new` changes, by config key
+    pub fn changes(&self, new: &ServerSettings) -> Vec<SettingChange> {
+        let mut diff = Diff {
+            old: self,
+            new,
+            changes: Vec::new(),
+        };
+
+        // Sockets, tasks and the compressor are all set up once
+        diff.restart("network.bind", |s| &s.bind);
+        diff.restart("network.port", |s| &s.port);
+        diff.restart("network.sockets", |s| &s.sockets);
+        diff.restart("network.worker_threads", |s| &s.worker_threads);
+        diff.restart("network.receive_workers", |s| &s.receive_workers);
+        diff.restart("network.receive_queue_depth", |s| &s.receive_queue_depth);
+        diff.restart("network.mtu", |s| &s.fragment.mtu);
+        diff.restart("network.compression", |s| &s.compression.enabled);
+        diff.restart("network.compression_threshold", |s| {
+            &s.compression.threshold
+        });
+        diff.restart("network.zstd_level", |s| &s.compression.zstd_level);
+        diff.restart("network.compression_dictionary", |s| {
+            &s.compression.dictionary
+        });
+        diff.live("network.bandwidth_budget", |s| &s.bandwidth.budget);
+        diff.live("network.min_bandwidth", |s| &s.bandwidth.min_rate);
+
+        diff.restart("timing.ping_interval_ms", |s| &s.liveness.ping_interval);
+        diff.restart("timing.scan_interval_secs", |s| &s.liveness.scan_interval);
+        diff.live("timing.inactivity_timeout_secs", |s| {
+            &s.liveness.inactivity_timeout
+        });
+        diff.live("timing.shutdown_grace_secs", |s| &s.shutdown_grace);
+
+        diff.live("rooms.max_rooms", |s| &s.rooms.max_rooms);
+        diff.live("rooms.max_players", |s| &s.rooms.max_players);
+
+        diff.live("security.fragment_timeout_ms", |s| &s.fragment.timeout);
+        diff.live("security.max_message_size", |s| {
+            &s.fragment.max_message_size
+        });
+        diff.live("security.max_pending_bytes_per_client", |s| {
+            &s.fragment.max_pending_bytes_per_client
+        });
+        diff.live("security.max_pending_bytes", |s| {
+            &s.fragment.max_pending_bytes
+        });
+        diff.live("security.banned", |s| &s.banned);
+
+        diff.live("gameplay.view_radius", |s| &s.interest.view_radius);
+        diff.live("gameplay.line_of_sight", |s| &s.interest.line_of_sight);
+        diff.live("gameplay.enemy_count_scale", |s| &s.balance.enemy_count);
+        diff.live("gameplay.enemy_health_scale", |s| &s.balance.enemy_health);
+        diff.live("gameplay.enemy_speed_scale", |s| &s.balance.enemy_speed);
+        diff.live("gameplay.enemy_damage_scale", |s| &s.balance.enemy_damage);
+        diff.live("gameplay.max_rewind_ms", |s| &s.lag.max_rewind);
+        diff.live("gameplay.interp_delay_ms", |s| &s.lag.interp_delay);
+        diff.live("gameplay.modes.standard", |s| &s.modes.standard);
+        diff.live("gameplay.modes.hardcore", |s| &s.modes.hardcore);
+        diff.live("gameplay.modes.casual", |s| &s.modes.casual);
+
+        diff.live("server.motd", |s| &s.motd);
+
+        diff.live("logging.filter", |s| &s.logging.filter);
+        diff.restart("logging.format", |s| &s.logging.format);
+
+        diff.changes
+    }
+}
+
+struct Diff<'a> {
+    old: &'a ServerSettings,
+    new: &'a ServerSettings,
+    changes: Vec<SettingChange>,
+}
+
+impl Diff<'_> {
+    fn restart<T: Debug>(&mut self, key: &'static str, field: impl Fn(&ServerSettings) -> &T) {
+        self.compare(key, true, field);
+    }
+
+    fn live<T: Debug>(&mut self, key: &'static str, field: impl Fn(&ServerSettings) -> &T) {
+        self.compare(key, false, field);
+    }
+
+    fn compare<T: Debug>(
+        &mut self,
+        key: &'static str,
+        needs_restart: bool,
+        field: impl Fn(&ServerSettings) -> &T,
+    ) {
+        let old = format!("{:?}", field(self.old));
+        let new = format!("{:?}", field(self.new));
+        if old != new {
+            self.changes.push(SettingChange {
+                key,
+                old,
+                new,
+                needs_restart,
+            });
+        }
+    }
+}
@@ -1,4 +1,12 @@
-use super::Position;
+use super::{Position, floor::Difficulty, map::TileMap};
+
+/// Enemies ignore players further away than this
+pub const AGGRO_RADIUS: f32 = 220.0;
+pub const ATTACK_RANGE: f32 = 18.0;
+pub const ATTACK_COOLDOWN_SECS: f32 = 1.0;
+
+/// Shots that pass within this distance of an enemy hit it
+pub const HIT_RADIUS: f32 = 12.0;
 
 #[derive(Debug)]
 pub struct Enemy {
@@ -6,4 +14,42 @@ pub struct Enemy {
     pub position: Position,
     pub health: i32,
     pub speed: f32,
+    pub damage: i32,
+    /// Seconds until the next attack is allowed
+    pub attack_cooldown: f32,
+}
+
+impl Enemy {
+    pub fn new(id: u32, position: Position, difficulty: &Difficulty) -> Self {
+        Enemy {
+            id,
+            position,
+            health: difficulty.enemy_health,
+            speed: difficulty.enemy_speed,
+            damage: difficulty.enemy_damage,
+            attack_cooldown: 0.0,
+        }
+    }
+
+    /// Walk straight at the target, sliding along walls
+    pub fn step_towards(&mut self, target: &Position, map: &TileMap, dt: f32) {
+        let distance = self.position.distance_to(target);
+        if distance <= ATTACK_RANGE {
+            return;
+        }
+
+        let step = (self.speed * dt).min(distance - ATTACK_RANGE);
+        let dx = (target.x - self.position.x) / distance * step;
+        let dy = (target.y - self.position.y) / distance * step;
+
+        let moved_x = Position::new(self.position.x + dx, self.position.y);
+        if map.is_walkable(&moved_x) {
+            self.position = moved_x;
+        }
+
+        let moved_y = Position::new(self.position.x, self.position.y + dy);
+        if map.is_walkable(&moved_y) {
+            self.position = moved_y;
+        }
+    }
 }
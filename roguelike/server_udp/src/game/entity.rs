@@ -1,13 +1,22 @@
+use super::{Position, inventory::ItemStack};
 
+pub type EntityId = u32;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum EntityKind {
+    /// Items lying on the map, picked up with INTERACT
+    Pickup(ItemStack),
 
-}
+    /// Rolls the chest loot table once when opened. Locked chests need a key.
+    Chest { opened: bool, locked: bool },
 
+    /// Leads to the next floor once the party is standing on it
+    Stairs,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Entity {
-    pub id: u32,
-    pub kind:EntityKind 
+    pub id: EntityId,
+    pub kind: EntityKind,
+    pub position: Position,
 }
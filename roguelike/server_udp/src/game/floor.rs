@@ -0,0 +1,107 @@
+use std::time::{Duration, Instant};
+
+use super::{Position, entity::EntityId, map::TileMap};
+
+/// Enemy and loot tuning for one dungeon floor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difficulty {
+    pub enemy_count: usize,
+    pub enemy_health: i32,
+    pub enemy_speed: f32,
+    pub enemy_damage: i32,
+    pub chest_count: usize,
+}
+
+impl Difficulty {
+    /// Floors get more crowded and enemies get tougher the deeper the party goes
+    pub fn for_depth(depth: u32) -> Self {
+        let level = depth.saturating_sub(1);
+
+        Difficulty {
+            enemy_count: 3 + 2 * level as usize,
+            enemy_health: 30 + (30 * level as i32) / 4,
+            enemy_speed: (60.0 + 5.0 * level as f32).min(110.0),
+            enemy_damage: 5 + 2 * level as i32,
+            chest_count: (2 + level as usize / 2).min(6),
+        }
+    }
+}
+
+/// Server-wide tuning on top of the depth curve, 1.0 leaves it as it is
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Balance {
+    pub enemy_count: f32,
+    pub enemy_health: f32,
+    pub enemy_speed: f32,
+    pub enemy_damage: f32,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Balance {
+            enemy_count: 1.0,
+            enemy_health: 1.0,
+            enemy_speed: 1.0,
+            enemy_damage: 1.0,
+        }
+    }
+}
+
+impl Difficulty {
+    pub fn scaled(self, balance: &Balance) -> Self {
+        Difficulty {
+            enemy_count: (self.enemy_count as f32 * balance.enemy_count).round() as usize,
+            enemy_health: ((self.enemy_health as f32 * balance.enemy_health).round() as i32).max(1),
+            enemy_speed: self.enemy_speed * balance.enemy_speed,
+            enemy_damage: (self.enemy_damage as f32 * balance.enemy_damage).round() as i32,
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Floor {
+    pub seed: u64,
+    pub map: TileMap,
+    pub stairs: EntityId,
+}
+
+impl Floor {
+    /// Stairs are spawned by the room once the layout exists
+    pub fn new(seed: u64) -> Self {
+        Floor {
+            seed,
+            map: TileMap::generate(seed),
+            stairs: 0,
+        }
+    }
+
+    /// The party always arrives in the first generated room
+    pub fn spawn_position(&self) -> Position {
+        self.map.room_center(0)
+    }
+}
+
+/// State that lives for the whole run, across floors
+#[derive(Debug)]
+pub struct RunState {
+    pub depth: u32,
+    pub started_at: Instant,
+    pub kills: u32,
+}
+
+impl Default for RunState {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            started_at: Instant::now(),
+            kills: 0,
+        }
+    }
+}
+
+impl RunState {
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+}
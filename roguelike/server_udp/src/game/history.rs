@@ -0,0 +1,121 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::config::globals;
+
+use super::{Position, entity::EntityId};
+
+/// How far back hit tests look, to match what the shooter was seeing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LagCompensation {
+    /// Targets are never rewound further back than this
+    pub max_rewind: Duration,
+    /// How far behind the server clients render other entities
+    pub interp_delay: Duration,
+}
+
+impl Default for LagCompensation {
+    fn default() -> Self {
+        LagCompensation {
+            max_rewind: globals::MAX_REWIND,
+            interp_delay: globals::CLIENT_INTERP_DELAY,
+        }
+    }
+}
+
+impl LagCompensation {
+    /// How far back to look for a shot from a client with this round trip
+    pub fn rewind(&self, rtt: Duration) -> Duration {
+        (rtt / 2 + self.interp_delay).min(self.max_rewind)
+    }
+
+    /// A little more than the rewind cap, so the oldest rewind still has a frame
+    pub fn history_age(&self) -> Duration {
+        self.max_rewind + Duration::from_millis(100)
+    }
+}
+
+#[derive(Debug)]
+struct HistoryFrame {
+    time: Instant,
+    positions: HashMap<EntityId, Position>,
+}
+
+/// Where every enemy was over the last few ticks, used to rewind targets for hit tests
+#[derive(Debug)]
+pub struct PositionHistory {
+    frames: VecDeque<HistoryFrame>,
+    max_age: Duration,
+}
+
+impl PositionHistory {
+    pub fn new(max_age: Duration) -> Self {
+        PositionHistory {
+            frames: VecDeque::new(),
+            max_age,
+        }
+    }
+
+    pub fn record(&mut self, time: Instant, positions: HashMap<EntityId, Position>) {
+        self.frames.push_back(HistoryFrame { time, positions });
+
+        while self
+            .frames
+            .front()
+            .is_some_and(|frame| time.duration_since(frame.time) > self.max_age)
+        {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Takes effect as new frames are recorded
+    pub fn set_max_age(&mut self, max_age: Duration) {
+        self.max_age = max_age;
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Positions at `time`, interpolated between the two frames around it.
+    /// Times older than the history are clamped to the oldest frame.
+    pub fn positions_at(&self, time: Instant) -> Option<HashMap<EntityId, Position>> {
+        let newest = self.frames.back()?;
+        if time >= newest.time {
+            return Some(newest.positions.clone());
+        }
+
+        let after_index = self.frames.iter().position(|frame| frame.time > time)?;
+        if after_index == 0 {
+            return Some(self.frames[0].positions.clone());
+        }
+
+        let before = &self.frames[after_index - 1];
+        let after = &self.frames[after_index];
+        let span = after.time.duration_since(before.time).as_secs_f32();
+        let t = if span > 0.0 {
+            time.duration_since(before.time).as_secs_f32() / span
+        } else {
+            1.0
+        };
+
+        // Enemies that only exist in one of the frames are taken as-is
+        let positions = after
+            .positions
+            .iter()
+            .map(|(id, to)| {
+                let position = match before.positions.get(id) {
+                    Some(from) => {
+                        Position::new(from.x + (to.x - from.x) * t, from.y + (to.y - from.y) * t)
+                    }
+                    None => *to,
+                };
+                (*id, position)
+            })
+            .collect();
+
+        Some(positions)
+    }
+}
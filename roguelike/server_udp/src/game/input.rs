@@ -0,0 +1,162 @@
+use std::{collections::VecDeque, time::Instant};
+
+use crate::{config::globals, utils};
+
+use super::player::InputSeq;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputAction {
+    Move(f32, f32),
+    Shoot(f32, f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputCommand {
+    pub seq: InputSeq,
+    pub action: InputAction,
+}
+
+/// Per-player counters, logged when the player leaves
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct InputStats {
+    pub applied: u32,
+    /// Redundant copies of inputs we already had
+    pub duplicates: u32,
+    /// Arrived after the server had already given up waiting for them
+    pub late: u32,
+    /// Never arrived in time, skipped over
+    pub dropped: u32,
+    /// Too far ahead of the last applied input to be real
+    pub rejected: u32,
+}
+
+#[derive(Debug)]
+struct BufferedInput {
+    command: InputCommand,
+    received_at: Instant,
+}
+
+/// Orders inputs by sequence number and hands them out one tick at a time.
+/// Each datagram repeats the last few inputs, so a lost packet is usually covered by the next.
+#[derive(Debug, Default)]
+pub struct InputBuffer {
+    /// Set by the first input, clients do not have to start counting at zero
+    started: bool,
+    last_applied: InputSeq,
+    pending: VecDeque<BufferedInput>,
+    /// Sequence numbers we skipped, kept around to tell late inputs from duplicates
+    skipped: VecDeque<InputSeq>,
+    stalled_ticks: u32,
+    stats: InputStats,
+}
+
+impl InputBuffer {
+    pub fn last_applied(&self) -> InputSeq {
+        self.last_applied
+    }
+
+    pub fn stats(&self) -> InputStats {
+        self.stats
+    }
+
+    pub fn receive(&mut self, commands: impl IntoIterator<Item = InputCommand>, now: Instant) {
+        for command in commands {
+            if !self.started {
+                self.started = true;
+                self.last_applied = command.seq.wrapping_sub(1);
+            }
+
+            if !utils::sequence_newer(command.seq, self.last_applied) {
+                match self.skipped.iter().position(|seq| *seq == command.seq) {
+                    Some(index) => {
+                        self.skipped.remove(index);
+                        self.stats.late += 1;
+                    }
+                    None => self.stats.duplicates += 1,
+                }
+                continue;
+            }
+
+            if command.seq.wrapping_sub(self.last_applied) > globals::MAX_INPUT_LEAD {
+                self.stats.rejected += 1;
+                continue;
+            }
+
+            if self.pending.iter().any(|b| b.command.seq == command.seq) {
+                self.stats.duplicates += 1;
+                continue;
+            }
+
+            if self.pending.len() >= globals::MAX_BUFFERED_INPUTS {
+                self.stats.dropped += 1;
+                continue;
+            }
+
+            // Keep the queue sorted, new inputs almost always go at the back
+            let index = self
+                .pending
+                .iter()
+                .rposition(|b| utils::sequence_newer(command.seq, b.command.seq))
+                .map_or(0, |i| i + 1);
+            self.pending.insert(
+                index,
+                BufferedInput {
+                    command,
+                    received_at: now,
+                },
+            );
+        }
+    }
+
+    /// Inputs to apply this tick, in order, with the time each one arrived.
+    /// Normally one input per tick; a gap is waited on for a few ticks before it is skipped,
+    /// and a backlog is worked off faster so latency does not build up.
+    pub fn next_for_tick(&mut self) -> Vec<(InputCommand, Instant)> {
+        let mut ready = Vec::new();
+
+        let Some(front_seq) = self.pending.front().map(|b| b.command.seq) else {
+            self.stalled_ticks = 0;
+            return ready;
+        };
+
+        let expected = self.last_applied.wrapping_add(1);
+        if front_seq != expected {
+            self.stalled_ticks += 1;
+            if self.stalled_ticks <= globals::INPUT_GAP_TOLERANCE_TICKS {
+                return ready;
+            }
+
+            // Give up on the missing inputs, only the newest few can still turn up late
+            let gap = front_seq.wrapping_sub(expected);
+            self.stats.dropped = self.stats.dropped.saturating_add(gap);
+            let remembered = gap.min(globals::MAX_BUFFERED_INPUTS as u32);
+            for back in (1..=remembered).rev() {
+                self.skipped.push_back(front_seq.wrapping_sub(back));
+            }
+            while self.skipped.len() > globals::MAX_BUFFERED_INPUTS {
+                self.skipped.pop_front();
+            }
+            self.last_applied = front_seq.wrapping_sub(1);
+        }
+        self.stalled_ticks = 0;
+
+        let budget = 1 + self
+            .pending
+            .len()
+            .saturating_sub(globals::INPUT_CATCH_UP_THRESHOLD);
+
+        while ready.len() < budget {
+            match self.pending.front() {
+                Some(next) if next.command.seq == self.last_applied.wrapping_add(1) => {
+                    let next = self.pending.pop_front().unwrap();
+                    self.last_applied = next.command.seq;
+                    self.stats.applied += 1;
+                    ready.push((next.command, next.received_at));
+                }
+                _ => break,
+            }
+        }
+
+        ready
+    }
+}
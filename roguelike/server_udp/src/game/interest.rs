@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    Position,
+    entity::{Entity, EntityId},
+    map::{TILE_SIZE, Tile, TileMap},
+    player::PlayerID,
+    snapshot::RoomSnapshot,
+};
+
+/// Grid cells should be a bit smaller than the view radius so a query only touches a few
+const GRID_CELL_SIZE: f32 = 128.0;
+
+/// Priority every visible actor gains per tick it is left out of the snapshot, so nothing
+/// starves for long
+const BASE_PRIORITY: f32 = 1.0;
+/// Extra priority right next to the viewer, fading out at the edge of view
+const NEAR_PRIORITY: f32 = 2.0;
+/// Extra priority for an actor that moved or was hurt since this client last got it
+const CHANGED_PRIORITY: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterestSettings {
+    /// Clients only hear about things within this many world units
+    pub view_radius: f32,
+    /// Fog of war: also hide things behind walls
+    pub line_of_sight: bool,
+}
+
+impl Default for InterestSettings {
+    fn default() -> Self {
+        Self {
+            view_radius: 320.0,
+            line_of_sight: true,
+        }
+    }
+}
+
+/// Anything that moves and is streamed through snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActorId {
+    Player(PlayerID),
+    Enemy(EntityId),
+}
+
+/// What a client has been told exists, so we can send spawn/despawn on changes
+#[derive(Debug, Default)]
+pub struct ClientView {
+    pub actors: HashSet<ActorId>,
+    pub entities: HashSet<EntityId>,
+    /// Priority each visible actor built up since it was last in a snapshot
+    priorities: HashMap<ActorId, f32>,
+    /// Position and health each actor had in the last snapshot this client got
+    last_sent: HashMap<ActorId, (Position, i32)>,
+}
+
+impl ClientView {
+    /// Decide which of the visible actors make it into this tick's snapshot when only
+    /// `count` fit. Everyone gains priority, the winners start over from zero.
+    pub fn pick_updates(
+        &mut self,
+        viewer: &Position,
+        view_radius: f32,
+        candidates: impl Iterator<Item = (ActorId, Position, i32)>,
+        count: usize,
+    ) -> HashSet<ActorId> {
+        let mut ranked = Vec::new();
+
+        for (actor, position, health) in candidates {
+            let nearness = (1.0 - viewer.distance_to(&position) / view_radius).clamp(0.0, 1.0);
+            let changed = self.last_sent.get(&actor) != Some(&(position, health));
+
+            let priority = self.priorities.entry(actor).or_default();
+            *priority += BASE_PRIORITY + NEAR_PRIORITY * nearness;
+            if changed {
+                *priority += CHANGED_PRIORITY;
+            }
+
+            ranked.push((actor, *priority, position, health));
+        }
+
+        // Whatever is out of view starts from scratch when it comes back
+        let visible: HashSet<ActorId> = ranked.iter().map(|(actor, ..)| *actor).collect();
+        self.priorities.retain(|actor, _| visible.contains(actor));
+        self.last_sent.retain(|actor, _| visible.contains(actor));
+
+        if ranked.len() > count {
+            ranked.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+            ranked.truncate(count);
+        }
+
+        ranked
+            .into_iter()
+            .map(|(actor, _, position, health)| {
+                self.priorities.insert(actor, 0.0);
+                self.last_sent.insert(actor, (position, health));
+                actor
+            })
+            .collect()
+    }
+}
+
+/// Everything one client needs to hear about after a tick
+#[derive(Debug, Default)]
+pub struct ClientUpdate {
+    pub spawned_actors: Vec<ActorId>,
+    pub despawned_actors: Vec<ActorId>,
+    /// New to this client, or changed since it last heard about them
+    pub spawned_entities: Vec<Entity>,
+    pub despawned_entities: Vec<EntityId>,
+    pub snapshot: RoomSnapshot,
+}
+
+/// Uniform grid hash used to find what is near a position without scanning the whole room
+#[derive(Debug)]
+pub struct SpatialGrid<T> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<(T, Position)>>,
+}
+
+impl<T: Copy> Default for SpatialGrid<T> {
+    fn default() -> Self {
+        Self::new(GRID_CELL_SIZE)
+    }
+}
+
+impl<T: Copy> SpatialGrid<T> {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell(&self, position: &Position) -> (i32, i32) {
+        (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+        )
+    }
+
+    pub fn insert(&mut self, item: T, position: Position) {
+        let cell = self.cell(&position);
+        self.cells.entry(cell).or_default().push((item, position));
+    }
+
+    /// Everything within `radius` of `center`
+    pub fn query(&self, center: &Position, radius: f32) -> Vec<(T, Position)> {
+        let (min_x, min_y) = self.cell(&Position::new(center.x - radius, center.y - radius));
+        let (max_x, max_y) = self.cell(&Position::new(center.x + radius, center.y + radius));
+        let mut found = Vec::new();
+
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let Some(cell) = self.cells.get(&(x, y)) else {
+                    continue;
+                };
+
+                found.extend(
+                    cell.iter()
+                        .filter(|(_, position)| center.distance_to(position) <= radius),
+                );
+            }
+        }
+
+        found
+    }
+}
+
+/// March along the segment in half-tile steps and fail on the first wall
+pub fn has_line_of_sight(map: &TileMap, from: &Position, to: &Position) -> bool {
+    let distance = from.distance_to(to);
+    let steps = (distance / (TILE_SIZE * 0.5)).ceil() as usize;
+
+    (1..steps).all(|step| {
+        let t = step as f32 / steps as f32;
+        let point = Position::new(from.x + (to.x - from.x) * t, from.y + (to.y - from.y) * t);
+        map.tile_at(&point) != Tile::Wall
+    })
+}
@@ -0,0 +1,155 @@
+use std::{error::Error, fmt};
+
+use super::item::{self, ItemDef, ItemId};
+
+pub const INVENTORY_SLOTS: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItemStack {
+    pub item: ItemId,
+    pub count: u16,
+}
+
+impl ItemStack {
+    pub fn new(item: ItemId, count: u16) -> Self {
+        ItemStack { item, count }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InventoryError {
+    UnknownItem(ItemId),
+    InvalidSlot(u8),
+    EmptySlot(u8),
+    NotEnoughItems { slot: u8, requested: u16 },
+    NotEquippable(u8),
+    NotUsable(u8),
+    Full,
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InventoryError::UnknownItem(id) => write!(f, "Unknown item {id}"),
+            InventoryError::InvalidSlot(slot) => write!(f, "Invalid inventory slot {slot}"),
+            InventoryError::EmptySlot(slot) => write!(f, "Inventory slot {slot} is empty"),
+            InventoryError::NotEnoughItems { slot, requested } => {
+                write!(f, "Slot {slot} holds fewer than {requested} items")
+            }
+            InventoryError::NotEquippable(slot) => {
+                write!(f, "Item in slot {slot} can't be equipped")
+            }
+            InventoryError::NotUsable(slot) => write!(f, "Item in slot {slot} can't be used"),
+            InventoryError::Full => write!(f, "Inventory is full"),
+        }
+    }
+}
+
+impl Error for InventoryError {}
+
+/// Fixed-size slot inventory owned by a single player
+#[derive(Debug, Clone, PartialEq)]
+pub struct Inventory {
+    pub slots: [Option<ItemStack>; INVENTORY_SLOTS],
+    pub equipped: Option<u8>,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self {
+            slots: [None; INVENTORY_SLOTS],
+            equipped: None,
+        }
+    }
+}
+
+impl Inventory {
+    /// Add a stack, topping up existing stacks first. Returns whatever did not fit.
+    pub fn add(&mut self, stack: ItemStack) -> Result<Option<ItemStack>, InventoryError> {
+        let def = item::item_def(stack.item).ok_or(InventoryError::UnknownItem(stack.item))?;
+        let mut remaining = stack.count;
+
+        for slot in self.slots.iter_mut().flatten() {
+            if remaining == 0 {
+                break;
+            }
+            if slot.item == stack.item && slot.count < def.max_stack {
+                let moved = remaining.min(def.max_stack - slot.count);
+                slot.count += moved;
+                remaining -= moved;
+            }
+        }
+
+        for slot in self.slots.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            if slot.is_none() {
+                let moved = remaining.min(def.max_stack);
+                *slot = Some(ItemStack::new(stack.item, moved));
+                remaining -= moved;
+            }
+        }
+
+        if remaining == stack.count {
+            return Err(InventoryError::Full);
+        }
+
+        Ok((remaining > 0).then(|| ItemStack::new(stack.item, remaining)))
+    }
+
+    pub fn get(&self, slot: u8) -> Result<&ItemStack, InventoryError> {
+        self.slots
+            .get(slot as usize)
+            .ok_or(InventoryError::InvalidSlot(slot))?
+            .as_ref()
+            .ok_or(InventoryError::EmptySlot(slot))
+    }
+
+    pub fn find(&self, item: ItemId) -> Option<u8> {
+        self.slots
+            .iter()
+            .position(|slot| slot.is_some_and(|stack| stack.item == item))
+            .map(|slot| slot as u8)
+    }
+
+    /// Take `count` items out of a slot, clearing the slot (and the equip) when it runs out
+    pub fn remove(&mut self, slot: u8, count: u16) -> Result<ItemStack, InventoryError> {
+        let current = *self.get(slot)?;
+        if count == 0 || count > current.count {
+            return Err(InventoryError::NotEnoughItems {
+                slot,
+                requested: count,
+            });
+        }
+
+        if count == current.count {
+            self.slots[slot as usize] = None;
+            if self.equipped == Some(slot) {
+                self.equipped = None;
+            }
+        } else if let Some(stack) = self.slots[slot as usize].as_mut() {
+            stack.count -= count;
+        }
+
+        Ok(ItemStack::new(current.item, count))
+    }
+
+    pub fn equip(&mut self, slot: u8) -> Result<(), InventoryError> {
+        let stack = self.get(slot)?;
+        let def = item::item_def(stack.item).ok_or(InventoryError::UnknownItem(stack.item))?;
+
+        if !def.is_equippable() {
+            return Err(InventoryError::NotEquippable(slot));
+        }
+
+        self.equipped = Some(slot);
+        Ok(())
+    }
+
+    pub fn equipped_item(&self) -> Option<&'static ItemDef> {
+        let slot = self.equipped?;
+        let stack = self.get(slot).ok()?;
+        item::item_def(stack.item)
+    }
+}
@@ -0,0 +1,100 @@
+pub type ItemId = u16;
+
+/// What happens when a consumable is used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConsumableEffect {
+    Heal(i32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ItemKind {
+    Weapon {
+        damage: i32,
+        cooldown_ms: u32,
+        /// How far a shot reaches, in world units
+        range: f32,
+    },
+    Consumable(ConsumableEffect),
+    Key,
+}
+
+#[derive(Debug)]
+pub struct ItemDef {
+    pub id: ItemId,
+    pub name: &'static str,
+    pub kind: ItemKind,
+    pub max_stack: u16,
+}
+
+impl ItemDef {
+    pub fn is_equippable(&self) -> bool {
+        matches!(self.kind, ItemKind::Weapon { .. })
+    }
+}
+
+pub mod items {
+    use super::ItemId;
+
+    pub const RUSTY_SWORD: ItemId = 1;
+    pub const SHORT_BOW: ItemId = 2;
+    pub const FIRE_STAFF: ItemId = 3;
+    pub const HEALTH_POTION: ItemId = 10;
+    pub const LARGE_HEALTH_POTION: ItemId = 11;
+    pub const BRONZE_KEY: ItemId = 20;
+}
+
+/// Every item that exists in the game. Ids are sent over the wire so never reuse one.
+pub const ITEMS: &[ItemDef] = &[
+    ItemDef {
+        id: items::RUSTY_SWORD,
+        name: "Rusty Sword",
+        kind: ItemKind::Weapon {
+            damage: 10,
+            cooldown_ms: 400,
+            range: 40.0,
+        },
+        max_stack: 1,
+    },
+    ItemDef {
+        id: items::SHORT_BOW,
+        name: "Short Bow",
+        kind: ItemKind::Weapon {
+            damage: 7,
+            cooldown_ms: 250,
+            range: 400.0,
+        },
+        max_stack: 1,
+    },
+    ItemDef {
+        id: items::FIRE_STAFF,
+        name: "Fire Staff",
+        kind: ItemKind::Weapon {
+            damage: 18,
+            cooldown_ms: 700,
+            range: 300.0,
+        },
+        max_stack: 1,
+    },
+    ItemDef {
+        id: items::HEALTH_POTION,
+        name: "Health Potion",
+        kind: ItemKind::Consumable(ConsumableEffect::Heal(30)),
+        max_stack: 10,
+    },
+    ItemDef {
+        id: items::LARGE_HEALTH_POTION,
+        name: "Large Health Potion",
+        kind: ItemKind::Consumable(ConsumableEffect::Heal(75)),
+        max_stack: 5,
+    },
+    ItemDef {
+        id: items::BRONZE_KEY,
+        name: "Bronze Key",
+        kind: ItemKind::Key,
+        max_stack: 5,
+    },
+];
+
+pub fn item_def(id: ItemId) -> Option<&'static ItemDef> {
+    ITEMS.iter().find(|def| def.id == id)
+}
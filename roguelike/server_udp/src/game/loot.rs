@@ -0,0 +1,116 @@
+use rand::Rng;
+
+use super::{
+    inventory::ItemStack,
+    item::{ItemId, items},
+};
+
+#[derive(Debug)]
+pub struct LootEntry {
+    /// `None` is an empty roll
+    pub item: Option<ItemId>,
+    pub weight: u32,
+    pub min: u16,
+    pub max: u16,
+}
+
+#[derive(Debug)]
+pub struct LootTable {
+    pub rolls: u8,
+    pub entries: &'static [LootEntry],
+}
+
+impl LootTable {
+    /// Roll the table `rolls` times with the room RNG so drops stay reproducible per seed
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<ItemStack> {
+        let total_weight: u32 = self.entries.iter().map(|e| e.weight).sum();
+        let mut drops = Vec::new();
+
+        if total_weight == 0 {
+            return drops;
+        }
+
+        for _ in 0..self.rolls {
+            let mut pick = rng.random_range(0..total_weight);
+
+            for entry in self.entries {
+                if pick < entry.weight {
+                    if let Some(item) = entry.item {
+                        let count = rng.random_range(entry.min..=entry.max);
+                        drops.push(ItemStack::new(item, count));
+                    }
+                    break;
+                }
+                pick -= entry.weight;
+            }
+        }
+
+        drops
+    }
+}
+
+pub const ENEMY_LOOT: LootTable = LootTable {
+    rolls: 1,
+    entries: &[
+        LootEntry {
+            item: None,
+            weight: 60,
+            min: 0,
+            max: 0,
+        },
+        LootEntry {
+            item: Some(items::HEALTH_POTION),
+            weight: 25,
+            min: 1,
+            max: 2,
+        },
+        LootEntry {
+            item: Some(items::BRONZE_KEY),
+            weight: 10,
+            min: 1,
+            max: 1,
+        },
+        LootEntry {
+            item: Some(items::RUSTY_SWORD),
+            weight: 5,
+            min: 1,
+            max: 1,
+        },
+    ],
+};
+
+pub const CHEST_LOOT: LootTable = LootTable {
+    rolls: 2,
+    entries: &[
+        LootEntry {
+            item: Some(items::HEALTH_POTION),
+            weight: 35,
+            min: 1,
+            max: 3,
+        },
+        LootEntry {
+            item: Some(items::LARGE_HEALTH_POTION),
+            weight: 20,
+            min: 1,
+            max: 1,
+        },
+        LootEntry {
+            item: Some(items::SHORT_BOW),
+            weight: 20,
+            min: 1,
+            max: 1,
+        },
+        LootEntry {
+            item: Some(items::FIRE_STAFF),
+            weight: 10,
+            min: 1,
+            max: 1,
+        },
+        LootEntry {
+            item: Some(items::BRONZE_KEY),
+            weight: 15,
+            min: 1,
+            max: 2,
+        },
+    ],
+};
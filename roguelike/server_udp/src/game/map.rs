@@ -0,0 +1,171 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use super::Position;
+
+/// World units per tile
+pub const TILE_SIZE: f32 = 16.0;
+
+pub const MAP_WIDTH: usize = 64;
+pub const MAP_HEIGHT: usize = 64;
+
+const MAX_ROOMS: usize = 12;
+const MIN_ROOM_SIZE: usize = 5;
+const MAX_ROOM_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tile {
+    Wall,
+    Floor,
+}
+
+/// Rectangle of floor tiles carved out by the generator, in tile coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    fn intersects(&self, other: &Rect) -> bool {
+        // Keep one wall tile between rooms
+        self.x <= other.x + other.width
+            && other.x <= self.x + self.width
+            && self.y <= other.y + other.height
+            && other.y <= self.y + self.height
+    }
+
+    pub fn center(&self) -> (usize, usize) {
+        (self.x + self.width / 2, self.y + self.height / 2)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TileMap {
+    pub width: usize,
+    pub height: usize,
+    pub rooms: Vec<Rect>,
+    tiles: Vec<Tile>,
+}
+
+impl TileMap {
+    /// Carve rooms joined by L-shaped corridors. The same seed always gives the same layout.
+    pub fn generate(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut map = TileMap {
+            width: MAP_WIDTH,
+            height: MAP_HEIGHT,
+            rooms: Vec::new(),
+            tiles: vec![Tile::Wall; MAP_WIDTH * MAP_HEIGHT],
+        };
+
+        for _ in 0..MAX_ROOMS * 4 {
+            if map.rooms.len() == MAX_ROOMS {
+                break;
+            }
+
+            let width = rng.random_range(MIN_ROOM_SIZE..=MAX_ROOM_SIZE);
+            let height = rng.random_range(MIN_ROOM_SIZE..=MAX_ROOM_SIZE);
+            let room = Rect {
+                x: rng.random_range(1..map.width - width - 1),
+                y: rng.random_range(1..map.height - height - 1),
+                width,
+                height,
+            };
+
+            if map.rooms.iter().any(|other| room.intersects(other)) {
+                continue;
+            }
+
+            map.carve_room(&room);
+
+            if let Some(previous) = map.rooms.last() {
+                let (x1, y1) = previous.center();
+                let (x2, y2) = room.center();
+
+                if rng.random_bool(0.5) {
+                    map.carve_horizontal(x1, x2, y1);
+                    map.carve_vertical(y1, y2, x2);
+                } else {
+                    map.carve_vertical(y1, y2, x1);
+                    map.carve_horizontal(x1, x2, y2);
+                }
+            }
+
+            map.rooms.push(room);
+        }
+
+        map
+    }
+
+    fn set(&mut self, x: usize, y: usize, tile: Tile) {
+        self.tiles[y * self.width + x] = tile;
+    }
+
+    fn carve_room(&mut self, room: &Rect) {
+        for y in room.y..room.y + room.height {
+            for x in room.x..room.x + room.width {
+                self.set(x, y, Tile::Floor);
+            }
+        }
+    }
+
+    fn carve_horizontal(&mut self, x1: usize, x2: usize, y: usize) {
+        for x in x1.min(x2)..=x1.max(x2) {
+            self.set(x, y, Tile::Floor);
+        }
+    }
+
+    fn carve_vertical(&mut self, y1: usize, y2: usize, x: usize) {
+        for y in y1.min(y2)..=y1.max(y2) {
+            self.set(x, y, Tile::Floor);
+        }
+    }
+
+    pub fn tile(&self, x: usize, y: usize) -> Tile {
+        if x >= self.width || y >= self.height {
+            return Tile::Wall;
+        }
+        self.tiles[y * self.width + x]
+    }
+
+    pub fn tile_at(&self, position: &Position) -> Tile {
+        if position.x < 0.0 || position.y < 0.0 {
+            return Tile::Wall;
+        }
+
+        self.tile(
+            (position.x / TILE_SIZE) as usize,
+            (position.y / TILE_SIZE) as usize,
+        )
+    }
+
+    pub fn is_walkable(&self, position: &Position) -> bool {
+        self.tile_at(position) == Tile::Floor
+    }
+
+    /// World position at the middle of a tile
+    pub fn tile_center(x: usize, y: usize) -> Position {
+        Position::new((x as f32 + 0.5) * TILE_SIZE, (y as f32 + 0.5) * TILE_SIZE)
+    }
+
+    pub fn room_center(&self, index: usize) -> Position {
+        let (x, y) = self.rooms[index].center();
+        Self::tile_center(x, y)
+    }
+
+    /// Random floor position inside one of the generated rooms
+    pub fn random_floor_position<R: Rng + ?Sized>(&self, rng: &mut R) -> Position {
+        let index = rng.random_range(0..self.rooms.len());
+        self.random_position_in_room(rng, index)
+    }
+
+    pub fn random_position_in_room<R: Rng + ?Sized>(&self, rng: &mut R, index: usize) -> Position {
+        let room = &self.rooms[index];
+        Self::tile_center(
+            rng.random_range(room.x..room.x + room.width),
+            rng.random_range(room.y..room.y + room.height),
+        )
+    }
+}
@@ -1,10 +1,30 @@
-pub mod room;
-pub mod player;
 pub mod enemy;
 pub mod entity;
+pub mod floor;
+pub mod history;
+pub mod input;
+pub mod interest;
+pub mod inventory;
+pub mod item;
+pub mod loot;
+pub mod map;
+pub mod mode;
+pub mod player;
+pub mod room;
+pub mod snapshot;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Position {
     pub x: f32,
     pub y: f32,
 }
+
+impl Position {
+    pub fn new(x: f32, y: f32) -> Self {
+        Position { x, y }
+    }
+
+    pub fn distance_to(&self, other: &Position) -> f32 {
+        ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
+    }
+}
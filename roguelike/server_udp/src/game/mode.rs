@@ -0,0 +1,86 @@
+/// Rules the room follows when a player runs out of health
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeathRules {
+    /// Without a downed state players die the moment they hit 0 HP
+    pub downed_enabled: bool,
+    /// Seconds a downed player lasts before dying for good
+    pub bleed_out_secs: f32,
+    /// How close a teammate has to stay to revive
+    pub revive_radius: f32,
+    /// Seconds a teammate has to stay close to finish the revive
+    pub revive_secs: f32,
+    pub revive_health: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GameMode {
+    #[default]
+    Standard,
+    Hardcore,
+    Casual,
+}
+
+impl GameMode {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(GameMode::Standard),
+            1 => Some(GameMode::Hardcore),
+            2 => Some(GameMode::Casual),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            GameMode::Standard => 0,
+            GameMode::Hardcore => 1,
+            GameMode::Casual => 2,
+        }
+    }
+}
+
+/// Death rules of each game mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModeSettings {
+    pub standard: DeathRules,
+    pub hardcore: DeathRules,
+    pub casual: DeathRules,
+}
+
+impl Default for ModeSettings {
+    fn default() -> Self {
+        ModeSettings {
+            standard: DeathRules {
+                downed_enabled: true,
+                bleed_out_secs: 30.0,
+                revive_radius: 32.0,
+                revive_secs: 3.0,
+                revive_health: 30,
+            },
+            hardcore: DeathRules {
+                downed_enabled: false,
+                bleed_out_secs: 0.0,
+                revive_radius: 0.0,
+                revive_secs: 0.0,
+                revive_health: 0,
+            },
+            casual: DeathRules {
+                downed_enabled: true,
+                bleed_out_secs: 60.0,
+                revive_radius: 48.0,
+                revive_secs: 1.5,
+                revive_health: 60,
+            },
+        }
+    }
+}
+
+impl ModeSettings {
+    pub fn death_rules(&self, mode: GameMode) -> DeathRules {
+        match mode {
+            GameMode::Standard => self.standard,
+            GameMode::Hardcore => self.hardcore,
+            GameMode::Casual => self.casual,
+        }
+    }
+}
@@ -1,10 +1,77 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use super::Position;
+use super::{
+    Position,
+    input::InputBuffer,
+    inventory::{Inventory, ItemStack},
+    item::items,
+    map::TileMap,
+    mode::DeathRules,
+    room::RoomId,
+};
 
 pub type PlayerID = u32;
 pub type PlayerName = String;
 
+/// Client-assigned number on every PLAYER_INPUT, starting at 1
+pub type InputSeq = u32;
+
+pub const PLAYER_MAX_HEALTH: i32 = 100;
+
+/// World units per second
+pub const PLAYER_SPEED: f32 = 120.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LifeState {
+    Alive,
+    /// Waiting for a teammate. Times are in seconds.
+    Downed {
+        bleed_out: f32,
+        revive_progress: f32,
+    },
+    /// Spectating until the run is over
+    Dead,
+}
+
+impl LifeState {
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            LifeState::Alive => 0,
+            LifeState::Downed { .. } => 1,
+            LifeState::Dead => 2,
+        }
+    }
+}
+
+/// Why a player left, so peers can tell a quit from a dropped connection
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LeaveReason {
+    #[default]
+    Quit,
+    Timeout,
+    /// Removed by the server, like when its address is banned
+    Kicked,
+}
+
+impl LeaveReason {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(LeaveReason::Quit),
+            1 => Some(LeaveReason::Timeout),
+            2 => Some(LeaveReason::Kicked),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            LeaveReason::Quit => 0,
+            LeaveReason::Timeout => 1,
+            LeaveReason::Kicked => 2,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Player {
     pub player_name: PlayerName,
@@ -12,7 +79,15 @@ pub struct Player {
     pub position: Position,
     pub velocity: Position,
     pub health: i32,
-    pub last_active: Instant,
+    pub room_id: Option<RoomId>,
+    pub inventory: Inventory,
+    pub life: LifeState,
+    /// Smoothed round trip time of the player's session, refreshed by the room every tick
+    pub rtt: Duration,
+    pub last_shot: Option<Instant>,
+    /// Received inputs waiting for their tick. The newest applied one is echoed back
+    /// in snapshots for client reconciliation.
+    pub inputs: InputBuffer,
 }
 
 impl Default for Player {
@@ -22,16 +97,92 @@ impl Default for Player {
             id: 0,
             position: Position { x: 0.0, y: 0.0 },
             velocity: Position { x: 0.0, y: 0.0 },
-            health: 100,
-            last_active: Instant::now(),
+            health: PLAYER_MAX_HEALTH,
+            room_id: None,
+            inventory: Inventory::default(),
+            life: LifeState::Alive,
+            rtt: Duration::ZERO,
+            last_shot: None,
+            inputs: InputBuffer::default(),
         }
     }
 }
 
 impl Player {
     pub fn new(id: PlayerID) -> Self {
-        let mut player = Player::default();
-        player.id = id;
-        player
+        // Everyone starts with something to shoot with
+        let mut inventory = Inventory::default();
+        inventory.slots[0] = Some(ItemStack::new(items::SHORT_BOW, 1));
+        inventory.equipped = Some(0);
+
+        Player {
+            id,
+            inventory,
+            ..Default::default()
+        }
+    }
+
+    pub fn heal(&mut self, amount: i32) {
+        self.health = (self.health + amount).min(PLAYER_MAX_HEALTH);
+    }
+
+    /// Whole milliseconds, saturated to fit the scoreboard field
+    pub fn ping_ms(&self) -> u16 {
+        self.rtt.as_millis().min(u16::MAX as u128) as u16
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.life == LifeState::Alive
+    }
+
+    /// Apply damage and return true if that knocked the player out of the Alive state
+    pub fn take_damage(&mut self, amount: i32, rules: &DeathRules) -> bool {
+        if !self.is_alive() {
+            return false;
+        }
+
+        self.health = (self.health - amount).max(0);
+        if self.health > 0 {
+            return false;
+        }
+
+        self.velocity = Position::default();
+        self.life = if rules.downed_enabled {
+            LifeState::Downed {
+                bleed_out: rules.bleed_out_secs,
+                revive_progress: 0.0,
+            }
+        } else {
+            LifeState::Dead
+        };
+
+        true
+    }
+
+    /// Clients only send a direction, the server decides how fast that is
+    pub fn set_move_direction(&mut self, x: f32, y: f32) {
+        let length = (x * x + y * y).sqrt();
+
+        if !length.is_finite() || length == 0.0 {
+            self.velocity = Position::default();
+            return;
+        }
+
+        // Diagonal input must not be faster than straight input
+        let scale = PLAYER_SPEED / length.max(1.0);
+        self.velocity = Position::new(x * scale, y * scale);
+    }
+
+    /// Integrate velocity, sliding along walls one axis at a time
+    pub fn step(&mut self, map: &TileMap, dt: f32) {
+        let moved_x = Position::new(self.position.x + self.velocity.x * dt, self.position.y);
+        if map.is_walkable(&moved_x) {
+            self.position = moved_x;
+        }
+
+        let moved_y = Position::new(self.position.x, self.position.y + self.velocity.y * dt);
+        if map.is_walkable(&moved_y) {
+            self.position = moved_y;
+        }
     }
 }
@@ -1,18 +1,106 @@
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::Mutex;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
 
-use super::player::Player;
+use crate::config::globals;
+
+use super::{
+    Position,
+    enemy::{self, Enemy},
+    entity::{Entity, EntityId, EntityKind},
+    floor::{Balance, Difficulty, Floor, RunState},
+    history::{LagCompensation, PositionHistory},
+    input::InputAction,
+    interest::{self, ActorId, ClientUpdate, ClientView, InterestSettings, SpatialGrid},
+    inventory::ItemStack,
+    item::ItemKind,
+    loot::{self, LootTable},
+    mode::{DeathRules, GameMode},
+    player::{LifeState, Player, PlayerID},
+    snapshot::{
+        ENEMY_SNAPSHOT_BYTES, EnemySnapshot, PLAYER_SNAPSHOT_BYTES, PlayerSnapshot, RoomSnapshot,
+        SNAPSHOT_HEADER_BYTES,
+    },
+};
 
 pub type RoomId = u32;
 pub type RoomName = String;
 pub type RoomPass = String;
 
+/// Drops are scattered around the spot they came from so pickups don't stack
+const DROP_SCATTER: f32 = 16.0;
+
+/// Things that happened during a tick that the room's players need to hear about
+#[derive(Debug, PartialEq)]
+pub enum RoomEvent {
+    FloorChanged {
+        depth: u32,
+        seed: u64,
+        elapsed_ms: u64,
+        kills: u32,
+    },
+    PlayerStateChanged {
+        player_id: PlayerID,
+        life: LifeState,
+        health: i32,
+    },
+    RunEnded {
+        outcome: RunOutcome,
+        depth: u32,
+        elapsed_ms: u64,
+        kills: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunOutcome {
+    Defeat,
+    /// The server shut down before the run was over
+    Interrupted,
+}
+
+/// An enemy hit by a shot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShotHit {
+    pub enemy_id: EntityId,
+    pub killed: bool,
+}
+
+/// What the spatial grid indexes
+#[derive(Debug, Clone, Copy)]
+enum Indexed {
+    Enemy(EntityId),
+    Entity(EntityId),
+}
+
 #[derive(Debug)]
 pub struct Room {
     pub id: RoomId,
     pub room_name: RoomName,
     pub room_pass: RoomPass,
-    pub players: Mutex<HashMap<SocketAddr, Arc<Mutex<Player>>>>,
+    pub players: HashMap<SocketAddr, Player>,
+    pub entities: HashMap<EntityId, Entity>,
+    pub enemies: HashMap<EntityId, Enemy>,
+    pub rng: StdRng,
+    pub floor: Floor,
+    pub run: RunState,
+    pub descend_votes: HashSet<PlayerID>,
+    pub mode: GameMode,
+    pub death_rules: DeathRules,
+    /// Applies from the next floor when it changes mid-run
+    pub balance: Balance,
+    pub outcome: Option<RunOutcome>,
+    pub interest: InterestSettings,
+    pub lag: LagCompensation,
+    pub tick: u32,
+    pub history: PositionHistory,
+    views: HashMap<SocketAddr, ClientView>,
+    changed_entities: HashSet<EntityId>,
+    next_entity_id: EntityId,
 }
 
 impl Room {
@@ -20,13 +108,601 @@ impl Room {
         id: RoomId,
         room_name: RoomName,
         room_pass: RoomPass,
-        players: Mutex<HashMap<SocketAddr, Arc<Mutex<Player>>>>,
+        mode: GameMode,
+        balance: Balance,
+        death_rules: DeathRules,
     ) -> Self {
-        Room {
+        let mut rng = StdRng::from_os_rng();
+        let floor = Floor::new(rng.random());
+
+        let mut room = Room {
             id,
             room_name,
             room_pass,
-            players,
+            players: HashMap::new(),
+            entities: HashMap::new(),
+            enemies: HashMap::new(),
+            rng,
+            floor,
+            run: RunState::default(),
+            descend_votes: HashSet::new(),
+            mode,
+            death_rules,
+            balance,
+            outcome: None,
+            interest: InterestSettings::default(),
+            lag: LagCompensation::default(),
+            tick: 0,
+            history: PositionHistory::new(LagCompensation::default().history_age()),
+            views: HashMap::new(),
+            changed_entities: HashSet::new(),
+            next_entity_id: 1,
+        };
+
+        room.populate_floor();
+        room
+    }
+
+    /// Spawn stairs, chests and enemies for the current floor, scaled by depth
+    fn populate_floor(&mut self) {
+        let difficulty = Difficulty::for_depth(self.run.depth).scaled(&self.balance);
+        let room_count = self.floor.map.rooms.len();
+
+        let stairs_position = self.floor.map.room_center(room_count - 1);
+        self.floor.stairs = self.spawn_entity(EntityKind::Stairs, stairs_position).id;
+
+        for _ in 0..difficulty.chest_count {
+            let position = self.floor.map.random_floor_position(&mut self.rng);
+            let locked = self.rng.random_bool(0.25);
+            self.spawn_entity(
+                EntityKind::Chest {
+                    opened: false,
+                    locked,
+                },
+                position,
+            );
+        }
+
+        // Keep the arrival room free of enemies when there is anywhere else to put them
+        let first_enemy_room = if room_count > 1 { 1 } else { 0 };
+
+        for _ in 0..difficulty.enemy_count {
+            let index = self.rng.random_range(first_enemy_room..room_count);
+            let position = self.floor.map.random_position_in_room(&mut self.rng, index);
+
+            let id = self.next_entity_id();
+            self.enemies
+                .insert(id, Enemy::new(id, position, &difficulty));
+        }
+    }
+
+    /// Place a new member at the arrival point of the current floor
+    pub fn add_player(&mut self, addr: SocketAddr, mut player: Player) {
+        player.position = self.floor.spawn_position();
+        player.room_id = Some(self.id);
+        self.players.insert(addr, player);
+    }
+
+    pub fn remove_player(&mut self, addr: &SocketAddr) -> Option<Player> {
+        let player = self.players.remove(addr)?;
+        self.descend_votes.remove(&player.id);
+        self.views.remove(addr);
+        Some(player)
+    }
+
+    pub fn floor_changed_event(&self) -> RoomEvent {
+        RoomEvent::FloorChanged {
+            depth: self.run.depth,
+            seed: self.floor.seed,
+            elapsed_ms: self.run.elapsed().as_millis() as u64,
+            kills: self.run.kills,
+        }
+    }
+
+    /// Generate the next floor and move the whole party to its arrival room
+    pub fn descend(&mut self) -> RoomEvent {
+        self.run.depth += 1;
+        self.floor = Floor::new(self.rng.random());
+        self.entities.clear();
+        self.enemies.clear();
+        self.descend_votes.clear();
+        self.changed_entities.clear();
+        self.history.clear();
+        self.populate_floor();
+
+        // Clients throw away the old floor when they get FLOOR_CHANGED
+        for view in self.views.values_mut() {
+            *view = ClientView::default();
+        }
+
+        let spawn = self.floor.spawn_position();
+        for player in self.players.values_mut() {
+            player.position = spawn;
+            player.velocity = Position::default();
+        }
+
+        info!(depth = self.run.depth, "Room descended");
+
+        self.floor_changed_event()
+    }
+
+    /// End a run that is still going because the server is stopping
+    pub fn interrupt(&mut self) -> Option<RoomEvent> {
+        if self.outcome.is_some() {
+            return None;
+        }
+        self.outcome = Some(RunOutcome::Interrupted);
+
+        Some(RoomEvent::RunEnded {
+            outcome: RunOutcome::Interrupted,
+            depth: self.run.depth,
+            elapsed_ms: self.run.elapsed().as_millis() as u64,
+            kills: self.run.kills,
+        })
+    }
+
+    pub fn vote_descend(&mut self, player_id: PlayerID) {
+        self.descend_votes.insert(player_id);
+    }
+
+    /// Advance the room by `dt` seconds to server tick `tick`
+    pub fn tick(&mut self, tick: u32, dt: f32) -> Vec<RoomEvent> {
+        if self.outcome.is_some() {
+            return Vec::new();
+        }
+
+        // Taken out for the tick so players and the rest of the room borrow separately
+        let mut members = std::mem::take(&mut self.players);
+        let mut players: Vec<&mut Player> = members.values_mut().collect();
+
+        let mut events = Vec::new();
+        self.tick = tick;
+
+        for player in players.iter_mut() {
+            // Inputs are consumed even while downed so acknowledgements keep moving
+            for (command, received_at) in player.inputs.next_for_tick() {
+                if !player.is_alive() {
+                    continue;
+                }
+
+                match command.action {
+                    InputAction::Move(x, y) => player.set_move_direction(x, y),
+                    InputAction::Shoot(x, y) => {
+                        self.player_shoot(player, Position::new(x, y), received_at)
+                    }
+                }
+            }
+
+            if player.is_alive() {
+                player.step(&self.floor.map, dt);
+            }
+        }
+
+        self.update_enemies(&mut players, dt, &mut events);
+        self.update_downed(&mut players, dt, &mut events);
+
+        self.history.set_max_age(self.lag.history_age());
+        self.history.record(
+            Instant::now(),
+            self.enemies
+                .values()
+                .map(|enemy| (enemy.id, enemy.position))
+                .collect(),
+        );
+
+        let stairs = self.entities.get(&self.floor.stairs).map(|e| e.position);
+        let living: Vec<&&mut Player> = players.iter().filter(|p| p.is_alive()).collect();
+
+        let on_stairs = living
+            .iter()
+            .filter(|p| {
+                stairs.is_some_and(|s| s.distance_to(&p.position) <= globals::STAIRS_RADIUS)
+            })
+            .count();
+        let votes = living
+            .iter()
+            .filter(|p| self.descend_votes.contains(&p.id))
+            .count();
+        let living_count = living.len();
+        let everyone_dead =
+            !players.is_empty() && players.iter().all(|p| p.life == LifeState::Dead);
+
+        self.players = members;
+
+        if everyone_dead {
+            self.outcome = Some(RunOutcome::Defeat);
+            info!(depth = self.run.depth, "Run ended in defeat");
+
+            events.push(RoomEvent::RunEnded {
+                outcome: RunOutcome::Defeat,
+                depth: self.run.depth,
+                elapsed_ms: self.run.elapsed().as_millis() as u64,
+                kills: self.run.kills,
+            });
+            return events;
+        }
+
+        // Every living member on the stairs, or a majority of them voted to move on
+        if living_count > 0 && (on_stairs == living_count || votes * 2 > living_count) {
+            events.push(self.descend());
+        }
+
+        events
+    }
+
+    /// Enemies chase the closest living player and hit whoever is in range
+    fn update_enemies(
+        &mut self,
+        players: &mut [&mut Player],
+        dt: f32,
+        events: &mut Vec<RoomEvent>,
+    ) {
+        for enemy in self.enemies.values_mut() {
+            enemy.attack_cooldown = (enemy.attack_cooldown - dt).max(0.0);
+
+            let target = players
+                .iter_mut()
+                .filter(|p| p.is_alive())
+                .map(|p| (enemy.position.distance_to(&p.position), p))
+                .filter(|(distance, _)| *distance <= enemy::AGGRO_RADIUS)
+                .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+            let Some((_, player)) = target else {
+                continue;
+            };
+
+            enemy.step_towards(&player.position, &self.floor.map, dt);
+
+            if enemy.attack_cooldown > 0.0
+                || enemy.position.distance_to(&player.position) > enemy::ATTACK_RANGE
+            {
+                continue;
+            }
+
+            enemy.attack_cooldown = enemy::ATTACK_COOLDOWN_SECS;
+            player.take_damage(enemy.damage, &self.death_rules);
+
+            events.push(RoomEvent::PlayerStateChanged {
+                player_id: player.id,
+                life: player.life,
+                health: player.health,
+            });
+        }
+    }
+
+    /// Downed players bleed out unless a living teammate stays close long enough
+    fn update_downed(&mut self, players: &mut [&mut Player], dt: f32, events: &mut Vec<RoomEvent>) {
+        let rules = self.death_rules;
+        let living: Vec<Position> = players
+            .iter()
+            .filter(|p| p.is_alive())
+            .map(|p| p.position)
+            .collect();
+
+        for player in players.iter_mut() {
+            let LifeState::Downed {
+                bleed_out,
+                revive_progress,
+            } = player.life
+            else {
+                continue;
+            };
+
+            let being_revived = living
+                .iter()
+                .any(|pos| pos.distance_to(&player.position) <= rules.revive_radius);
+
+            // Progress is lost as soon as the reviver walks away
+            let revive_progress = if being_revived {
+                revive_progress + dt
+            } else {
+                0.0
+            };
+            let bleed_out = bleed_out - dt;
+
+            if revive_progress >= rules.revive_secs {
+                player.life = LifeState::Alive;
+                player.health = rules.revive_health;
+                info!("Player {} was revived", player.id);
+            } else if bleed_out <= 0.0 {
+                player.life = LifeState::Dead;
+                info!("Player {} died", player.id);
+            } else {
+                player.life = LifeState::Downed {
+                    bleed_out,
+                    revive_progress,
+                };
+                continue;
+            }
+
+            events.push(RoomEvent::PlayerStateChanged {
+                player_id: player.id,
+                life: player.life,
+                health: player.health,
+            });
+        }
+    }
+
+    pub fn next_entity_id(&mut self) -> EntityId {
+        let id = self.next_entity_id;
+        self.next_entity_id = self.next_entity_id.wrapping_add(1).max(1);
+        id
+    }
+
+    pub fn spawn_entity(&mut self, kind: EntityKind, position: Position) -> &Entity {
+        let id = self.next_entity_id();
+        self.entities
+            .entry(id)
+            .or_insert(Entity { id, kind, position })
+    }
+
+    /// Put a stack on the floor near `position`
+    pub fn spawn_pickup(&mut self, stack: ItemStack, position: Position) -> Entity {
+        let scattered = Position::new(
+            position.x + self.rng.random_range(-DROP_SCATTER..=DROP_SCATTER),
+            position.y + self.rng.random_range(-DROP_SCATTER..=DROP_SCATTER),
+        );
+
+        self.spawn_entity(EntityKind::Pickup(stack), scattered)
+            .clone()
+    }
+
+    /// Roll a loot table and spawn every drop as a pickup
+    pub fn spawn_loot(&mut self, table: &LootTable, position: Position) -> Vec<Entity> {
+        let drops = table.roll(&mut self.rng);
+
+        drops
+            .into_iter()
+            .map(|stack| self.spawn_pickup(stack, position))
+            .collect()
+    }
+
+    /// Remove a dead enemy, count the kill and return the pickups it dropped
+    pub fn kill_enemy(&mut self, enemy_id: EntityId) -> Vec<Entity> {
+        match self.enemies.remove(&enemy_id) {
+            Some(enemy) => {
+                self.run.kills += 1;
+                self.spawn_loot(&loot::ENEMY_LOOT, enemy.position)
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Mark a chest as opened and return the chest plus its drops
+    pub fn open_chest(&mut self, entity_id: EntityId) -> Option<(Entity, Vec<Entity>)> {
+        let chest = self.entities.get_mut(&entity_id)?;
+
+        match &mut chest.kind {
+            EntityKind::Chest { opened, locked } if !*opened => {
+                *opened = true;
+                *locked = false;
+            }
+            _ => return None,
+        }
+
+        let chest = chest.clone();
+        self.mark_changed(entity_id);
+        let drops = self.spawn_loot(&loot::CHEST_LOOT, chest.position);
+
+        Some((chest, drops))
+    }
+
+    /// Fire the player's equipped weapon. Targets are rewound to what the shooter saw when
+    /// the input was sent: half a round trip plus the delay clients render others with.
+    fn player_shoot(&mut self, player: &mut Player, direction: Position, received_at: Instant) {
+        let Some(ItemKind::Weapon {
+            damage,
+            cooldown_ms,
+            range,
+        }) = player.inventory.equipped_item().map(|def| def.kind)
+        else {
+            return;
+        };
+
+        if player.last_shot.is_some_and(|last| {
+            received_at.saturating_duration_since(last) < Duration::from_millis(cooldown_ms as u64)
+        }) {
+            return;
+        }
+        player.last_shot = Some(received_at);
+
+        let rewind = self.lag.rewind(player.rtt);
+        let view_time = received_at.checked_sub(rewind).unwrap_or(received_at);
+
+        if let Some(hit) = self.resolve_shot(player.position, direction, damage, range, view_time) {
+            debug!(
+                "Player {} hit enemy {} (rewound {}ms){}",
+                player.id,
+                hit.enemy_id,
+                rewind.as_millis(),
+                if hit.killed { " and killed it" } else { "" }
+            );
+        }
+    }
+
+    /// Hitscan along `direction` against enemies as they were at `view_time`.
+    /// Damage lands on the live enemy, walls stop the shot.
+    pub fn resolve_shot(
+        &mut self,
+        origin: Position,
+        direction: Position,
+        damage: i32,
+        range: f32,
+        view_time: Instant,
+    ) -> Option<ShotHit> {
+        let length = (direction.x * direction.x + direction.y * direction.y).sqrt();
+        if !length.is_finite() || length == 0.0 {
+            return None;
+        }
+        let (dir_x, dir_y) = (direction.x / length, direction.y / length);
+
+        let targets = self.history.positions_at(view_time).unwrap_or_else(|| {
+            self.enemies
+                .values()
+                .map(|enemy| (enemy.id, enemy.position))
+                .collect()
+        });
+
+        let (enemy_id, distance) = targets
+            .iter()
+            .filter(|(id, _)| self.enemies.contains_key(id))
+            .filter_map(|(id, position)| {
+                let to_x = position.x - origin.x;
+                let to_y = position.y - origin.y;
+                let along = to_x * dir_x + to_y * dir_y;
+
+                if along < 0.0 || along > range {
+                    return None;
+                }
+
+                let closest = Position::new(origin.x + dir_x * along, origin.y + dir_y * along);
+                (closest.distance_to(position) <= enemy::HIT_RADIUS).then_some((*id, along))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+
+        let impact = Position::new(origin.x + dir_x * distance, origin.y + dir_y * distance);
+        if !interest::has_line_of_sight(&self.floor.map, &origin, &impact) {
+            return None;
+        }
+
+        let enemy = self.enemies.get_mut(&enemy_id)?;
+        enemy.health -= damage;
+        let killed = enemy.health <= 0;
+
+        if killed {
+            self.kill_enemy(enemy_id);
+        }
+
+        Some(ShotHit { enemy_id, killed })
+    }
+
+    /// Make sure clients that can already see this entity get its new state
+    pub fn mark_changed(&mut self, entity_id: EntityId) {
+        self.changed_entities.insert(entity_id);
+    }
+
+    /// Work out what each member can see this tick and what changed since the last one.
+    /// `budgets` caps each member's snapshot in bytes, members without one are not capped.
+    pub fn client_updates(
+        &mut self,
+        budgets: &HashMap<SocketAddr, usize>,
+    ) -> Vec<(SocketAddr, ClientUpdate)> {
+        let members: Vec<_> = self
+            .players
+            .iter()
+            .map(|(addr, player)| {
+                (
+                    *addr,
+                    PlayerSnapshot {
+                        id: player.id,
+                        position: player.position,
+                        velocity: player.velocity,
+                        health: player.health,
+                        ping_ms: player.ping_ms(),
+                        life: player.life,
+                    },
+                    player.inputs.last_applied(),
+                    budgets.get(addr).copied().unwrap_or(usize::MAX),
+                )
+            })
+            .collect();
+
+        let mut grid = SpatialGrid::default();
+        for enemy in self.enemies.values() {
+            grid.insert(Indexed::Enemy(enemy.id), enemy.position);
+        }
+        for entity in self.entities.values() {
+            grid.insert(Indexed::Entity(entity.id), entity.position);
+        }
+
+        let changed = std::mem::take(&mut self.changed_entities);
+        self.views
+            .retain(|addr, _| members.iter().any(|(member, ..)| member == addr));
+
+        let mut updates = Vec::with_capacity(members.len());
+
+        for (addr, viewer, last_input_seq, budget) in members.iter() {
+            // Teammates are always visible, everything else has to be close enough
+            let mut actors: HashSet<ActorId> = members
+                .iter()
+                .map(|(_, player, ..)| ActorId::Player(player.id))
+                .collect();
+            let mut entities = HashSet::new();
+
+            for (item, position) in grid.query(&viewer.position, self.interest.view_radius) {
+                if self.interest.line_of_sight
+                    && !interest::has_line_of_sight(&self.floor.map, &viewer.position, &position)
+                {
+                    continue;
+                }
+
+                match item {
+                    Indexed::Enemy(id) => actors.insert(ActorId::Enemy(id)),
+                    Indexed::Entity(id) => entities.insert(id),
+                };
+            }
+
+            let view = self.views.entry(*addr).or_default();
+
+            // Teammates always go out, enemies share what is left of the budget
+            let enemy_budget = budget
+                .saturating_sub(SNAPSHOT_HEADER_BYTES + members.len() * PLAYER_SNAPSHOT_BYTES);
+            let picked = view.pick_updates(
+                &viewer.position,
+                self.interest.view_radius,
+                self.enemies
+                    .values()
+                    .filter(|enemy| actors.contains(&ActorId::Enemy(enemy.id)))
+                    .map(|enemy| (ActorId::Enemy(enemy.id), enemy.position, enemy.health)),
+                enemy_budget / ENEMY_SNAPSHOT_BYTES,
+            );
+
+            let update = ClientUpdate {
+                spawned_actors: actors.difference(&view.actors).copied().collect(),
+                despawned_actors: view.actors.difference(&actors).copied().collect(),
+                spawned_entities: entities
+                    .iter()
+                    .filter(|id| !view.entities.contains(id) || changed.contains(id))
+                    .filter_map(|id| self.entities.get(id).cloned())
+                    .collect(),
+                despawned_entities: view.entities.difference(&entities).copied().collect(),
+                snapshot: RoomSnapshot {
+                    tick: self.tick,
+                    ack_input_seq: *last_input_seq,
+                    ack_position: viewer.position,
+                    players: members
+                        .iter()
+                        .map(|(_, player, ..)| player.clone())
+                        .collect(),
+                    enemies: self
+                        .enemies
+                        .values()
+                        .filter(|enemy| picked.contains(&ActorId::Enemy(enemy.id)))
+                        .map(|enemy| EnemySnapshot {
+                            id: enemy.id,
+                            position: enemy.position,
+                            health: enemy.health,
+                        })
+                        .collect(),
+                },
+            };
+
+            view.actors = actors;
+            view.entities = entities;
+            updates.push((*addr, update));
+        }
+
+        updates
+    }
+
+    /// Remove a pickup from the map, returning what it held
+    pub fn take_pickup(&mut self, entity_id: EntityId) -> Option<ItemStack> {
+        match self.entities.get(&entity_id)?.kind {
+            EntityKind::Pickup(stack) => {
+                self.entities.remove(&entity_id);
+                Some(stack)
+            }
+            _ => None,
         }
     }
 }
@@ -0,0 +1,41 @@
+use super::{
+    Position,
+    entity::EntityId,
+    player::{InputSeq, LifeState, PlayerID},
+};
+
+/// Rough encoded sizes, for budgeting a snapshot before it is encoded. A little over
+/// what small ids and healths actually take.
+pub const SNAPSHOT_HEADER_BYTES: usize = 16;
+pub const PLAYER_SNAPSHOT_BYTES: usize = 14;
+pub const ENEMY_SNAPSHOT_BYTES: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerSnapshot {
+    pub id: PlayerID,
+    pub position: Position,
+    pub velocity: Position,
+    pub health: i32,
+    /// Smoothed round trip time for the scoreboard
+    pub ping_ms: u16,
+    pub life: LifeState,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnemySnapshot {
+    pub id: EntityId,
+    pub position: Position,
+    pub health: i32,
+}
+
+/// Per-client view of a room for one tick. Only holds what the client is allowed to see.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RoomSnapshot {
+    pub tick: u32,
+    /// Last input from the receiving client that is already reflected in this snapshot
+    pub ack_input_seq: InputSeq,
+    /// The receiving player's authoritative position, to replay unacknowledged inputs on
+    pub ack_position: Position,
+    pub players: Vec<PlayerSnapshot>,
+    pub enemies: Vec<EnemySnapshot>,
+}
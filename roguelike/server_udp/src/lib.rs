@@ -0,0 +1,6 @@
+pub mod config;
+pub mod game;
+pub mod logging;
+pub mod network;
+pub mod server;
+pub mod utils;
@@ -0,0 +1,62 @@
+use std::{io::IsTerminal, sync::OnceLock};
+
+use tracing_subscriber::{
+    EnvFilter, Registry, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+use crate::config::settings::{LogFormat, LogSettings};
+
+/// Target of every datagram and message going in or out, off unless asked for with
+/// `packets=trace` (or `--trace`)
+pub const PACKETS: &str = "packets";
+
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// Levels per module, in `RUST_LOG` syntax like `info,server_udp::server=debug`
+pub fn filter(directives: &str) -> Result<EnvFilter, String> {
+    EnvFilter::builder()
+        .parse(directives)
+        .map_err(|e| format!("logging.filter ({directives}) is invalid: {e}"))
+}
+
+/// Install the global subscriber. Only the filter can change after this.
+pub fn init(settings: &LogSettings) -> Result<(), String> {
+    let (filter, handle) = reload::Layer::new(filter(&settings.filter)?);
+
+    let (text, json) = match settings.format {
+        LogFormat::Text => (
+            Some(fmt::layer().with_ansi(std::io::stdout().is_terminal())),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            ),
+        ),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .try_init()
+        .map_err(|e| format!("Can not set up logging: {e}"))?;
+
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+/// Swap the levels of a running server, a no-op when `init` was never called
+pub fn set_filter(directives: &str) -> Result<(), String> {
+    let filter = filter(directives)?;
+    match FILTER.get() {
+        Some(handle) => handle
+            .reload(filter)
+            .map_err(|e| format!("Can not change logging.filter: {e}")),
+        None => Ok(()),
+    }
+}
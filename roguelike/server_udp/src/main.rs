@@ -1,15 +1,23 @@
-use std::error::Error;
+use std::{
+    error::Error,
+    fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
 
 use clap::Parser;
-use config::globals;
-use network::message;
+use server_udp::{
+    config::{
+        file::ConfigFile,
+        globals,
+        settings::{LogFormat, ServerSettings},
+    },
+    logging,
+    server::{self, ServerHandle},
+};
 use tokio::runtime::Builder;
-
-pub mod config;
-pub mod game;
-pub mod network;
-pub mod server;
-pub mod utils;
+use tracing::{error, info, warn};
 
 #[derive(Parser, Debug)]
 #[command(about = "UDP server for roguelike game")]
@@ -18,52 +26,326 @@ struct Args {
         short,
         long,
         require_equals = true,
-        default_value_t = globals::DEFAULT_PORT,
-        help = "PORT NUMBER used for server init")]
-    port: u16,
+        help = "TOML file with server settings. Environment variables (ROGUELIKE_<SECTION>_<KEY>) override it, flags override both"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        require_equals = true,
+        help = format!("PORT NUMBER used for server init [default: {}]", globals::DEFAULT_PORT))]
+    port: Option<u16>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        value_delimiter = ',',
+        help = "Addresses to listen on, IPv4 or IPv6. Repeat or comma separate for several, :: alone is dual-stack [default: 0.0.0.0]"
+    )]
+    bind: Vec<IpAddr>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Sockets to open on the port with SO_REUSEPORT, each read by its own task [default: 1]"
+    )]
+    sockets: Option<usize>,
 
-    #[arg(short, long, help = "Enable tracing of UDP messages on console log.")]
+    #[arg(
+        long,
+        require_equals = true,
+        help = format!("Threads in the async runtime [default: {}]", globals::WORKER_THREADS)
+    )]
+    worker_threads: Option<usize>,
+
+    #[arg(
+        short,
+        long,
+        help = "Trace every packet sent and received, same as adding packets=trace to the log filter"
+    )]
     trace: bool,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = format!("Log levels per module, like info,server_udp::server=debug [default: {}]", globals::LOG_FILTER)
+    )]
+    log_filter: Option<String>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Log as text or json [default: text]"
+    )]
+    log_format: Option<LogFormat>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Milliseconds between pings to each client"
+    )]
+    ping_interval_ms: Option<u64>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Seconds between scans for inactive clients"
+    )]
+    scan_interval_secs: Option<u64>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Seconds of silence before a client is dropped"
+    )]
+    inactivity_timeout_secs: Option<u64>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Largest datagram to send, bigger messages are fragmented"
+    )]
+    mtu: Option<usize>,
+
+    #[arg(long, help = "Never compress messages, whatever clients offer")]
+    no_compression: bool,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Smallest message in bytes worth compressing"
+    )]
+    compression_threshold: Option<usize>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Zstd dictionary shared with clients, trained on recorded snapshots"
+    )]
+    compression_dictionary: Option<PathBuf>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Most bytes per second sent to one client"
+    )]
+    bandwidth_budget: Option<u32>,
+
+    #[arg(
+        long,
+        require_equals = true,
+        help = "Seconds running rooms get to finish when the server is asked to stop"
+    )]
+    shutdown_grace_secs: Option<u64>,
 }
 
-// Run server: cargo run -- --port=8082 --trace
+impl Args {
+    /// Defaults, then the config file, then the environment, then flags
+    fn settings(&self) -> Result<ServerSettings, String> {
+        let mut settings = ServerSettings::default();
+
+        if let Some(path) = &self.config {
+            ConfigFile::load(path)?.apply(&mut settings);
+        }
+
+        // Variables that are not unicode can not be ours
+        let vars = std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        });
+        ConfigFile::from_env(vars)?.apply(&mut settings);
+
+        if !self.bind.is_empty() {
+            settings.bind = self.bind.clone();
+        }
+        if let Some(port) = self.port {
+            settings.port = port;
+        }
+        if let Some(sockets) = self.sockets {
+            settings.sockets = sockets;
+        }
+        if let Some(threads) = self.worker_threads {
+            settings.worker_threads = threads;
+        }
+
+        if let Some(ms) = self.ping_interval_ms {
+            settings.liveness.ping_interval = Duration::from_millis(ms);
+        }
+        if let Some(secs) = self.scan_interval_secs {
+            settings.liveness.scan_interval = Duration::from_secs(secs);
+        }
+        if let Some(secs) = self.inactivity_timeout_secs {
+            settings.liveness.inactivity_timeout = Duration::from_secs(secs);
+        }
+
+        if let Some(mtu) = self.mtu {
+            settings.fragment.mtu = mtu;
+        }
+
+        if self.no_compression {
+            settings.compression.enabled = false;
+        }
+        if let Some(threshold) = self.compression_threshold {
+            settings.compression.threshold = threshold;
+        }
+        if let Some(dictionary) = &self.compression_dictionary {
+            settings.compression.dictionary = Some(dictionary.clone());
+        }
+
+        if let Some(budget) = self.bandwidth_budget {
+            settings.bandwidth.budget = budget;
+        }
+
+        if let Some(secs) = self.shutdown_grace_secs {
+            settings.shutdown_grace = Duration::from_secs(secs);
+        }
+
+        if let Some(filter) = &self.log_filter {
+            settings.logging.filter = filter.clone();
+        }
+        if let Some(format) = self.log_format {
+            settings.logging.format = format;
+        }
+        if self.trace {
+            settings.logging.filter =
+                format!("{},{}=trace", settings.logging.filter, logging::PACKETS);
+        }
+
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+/// Wait for CTRL + C, or SIGTERM where there is one
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "CTRL + C interrupt"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.map(|_| "CTRL + C interrupt")
+}
+
+/// Reload settings on SIGHUP, or when the config file changes
+async fn watch_config(args: &Args, server: &ServerHandle) {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    let mut last_modified: Option<SystemTime> = args.config.as_deref().and_then(modified);
+    let mut poll = tokio::time::interval(globals::CONFIG_POLL_INTERVAL);
+    let mut hangups = hangups();
+
+    loop {
+        tokio::select! {
+            _ = hangup(&mut hangups) => info!("SIGHUP received, reloading settings"),
+
+            _ = poll.tick() => {
+                let now_modified = args.config.as_deref().and_then(modified);
+                if now_modified == last_modified {
+                    continue;
+                }
+                last_modified = now_modified;
+                info!("Config file changed, reloading settings");
+            }
+        }
+
+        let result = match args.settings() {
+            Ok(settings) => server.reload(settings).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!("Settings not reloaded: {e}");
+        }
+    }
+}
+
+#[cfg(unix)]
+fn hangups() -> Option<tokio::signal::unix::Signal> {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    signal(SignalKind::hangup())
+        .inspect_err(|e| warn!("Failed to listen for SIGHUP: {e}"))
+        .ok()
+}
+
+#[cfg(not(unix))]
+fn hangups() -> Option<()> {
+    None
+}
+
+/// Resolves on every SIGHUP, never where there is none
+#[cfg(unix)]
+async fn hangup(hangups: &mut Option<tokio::signal::unix::Signal>) {
+    match hangups {
+        Some(signal) => {
+            signal.recv().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn hangup(_hangups: &mut Option<()>) {
+    std::future::pending().await
+}
+
+// Run server: cargo run -- --port=8082 --trace --log-filter=info,server_udp::server=debug
 fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
+    let settings = match args.settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Invalid settings: {e}");
+            std::process::exit(1);
+        }
+    };
+    let port = settings.port;
 
-    if args.trace {
-        message::set_trace(true);
-        println!("Message tracing enabled");
+    // Nothing is logged until this is done, so the errors above go straight to stderr
+    if let Err(e) = logging::init(&settings.logging) {
+        eprintln!("{e}");
+        std::process::exit(1);
     }
 
-    // Create tokio threadpool with 6 threads
     match Builder::new_multi_thread()
-        .worker_threads(6)
+        .worker_threads(settings.worker_threads)
         .enable_all()
         .build()
     {
         Ok(run_time) => {
-            println!("Tokio runtime successfully created");
+            info!("Tokio runtime successfully created");
             run_time.block_on(async {
                 // Start server here
-                match server::start_server(args.port).await {
-                    Ok(_) => {
-                        println!("Server started successfully on port {}. Waiting for CTRL + C to shutdown", args.port);
+                match server::start_server(settings).await {
+                    Ok(server) => {
+                        info!(port, "Server started successfully. Waiting for CTRL + C or SIGTERM to shutdown");
+
+                        tokio::select! {
+                            signal = shutdown_signal() => match signal {
+                                Ok(signal)=> info!("{signal} received. Shutting down server gracefully..."),
 
-                        match tokio::signal::ctrl_c().await {
-                            Ok(_)=> println!("\n CTRL + C interrupt received. Shutting down server gracefully..."),
+                                Err(e) => error!("Failed to listen for shutdown signals: {}", e),
+                            },
 
-                            Err(e) => eprintln!("Failed to listen for CTRL + C event: {}", e),
+                            _ = watch_config(&args, &server) => {}
                         }
+
+                        server.shutdown("Server is shutting down").await;
                     }
                     Err(e) => {
-                        eprintln!("Server failed to start: {:?}", e);
+                        error!("Server failed to start: {:?}", e);
                         std::process::exit(1);
                     }
                 }
             })
         }
         Err(err) => {
-            eprintln!("Something went wrong: {}", err);
+            error!("Something went wrong: {}", err);
             std::process::exit(1);
         }
     };
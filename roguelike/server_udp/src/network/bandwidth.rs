@@ -0,0 +1,200 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use crate::config::settings::BandwidthSettings;
+
+/// Unspent budget carried over is capped at this much sending time
+const BURST: Duration = Duration::from_millis(100);
+/// Snapshots not acked within this long count as lost
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_IN_FLIGHT: usize = 64;
+/// Smoothed loss above this backs the rate off
+const LOSS_THRESHOLD: f64 = 0.05;
+const DECREASE_FACTOR: f64 = 0.8;
+/// Backing off more often than this would react to the same loss twice
+const DECREASE_COOLDOWN: Duration = Duration::from_millis(250);
+/// Bytes per second won back for every snapshot delivered without loss
+const ADDITIVE_INCREASE: f64 = 256.0;
+
+#[derive(Debug, Clone, Copy)]
+struct SentSnapshot {
+    tick: u32,
+    /// Everything sent to the client between this snapshot and the next
+    bytes: usize,
+    sent_at: Instant,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BandwidthStats {
+    pub bytes_sent: u64,
+    pub bytes_acked: u64,
+    pub snapshots_acked: u64,
+    pub snapshots_lost: u64,
+}
+
+/// How fast one client can take data, learned from its SNAPSHOT_ACKs. Backs off when
+/// snapshots go missing and creeps back up to the budget while they arrive.
+/// Clients that never ack are sent at the full budget.
+#[derive(Debug, Clone)]
+pub struct BandwidthEstimator {
+    settings: BandwidthSettings,
+    /// Current send rate in bytes per second
+    rate: f64,
+    /// Bytes we may still send, refilled at `rate`
+    tokens: f64,
+    last_refill: Option<Instant>,
+    last_decrease: Option<Instant>,
+    in_flight: VecDeque<SentSnapshot>,
+    acking: bool,
+    loss: f64,
+    stats: BandwidthStats,
+}
+
+impl Default for BandwidthEstimator {
+    fn default() -> Self {
+        Self::new(BandwidthSettings::default())
+    }
+}
+
+impl BandwidthEstimator {
+    pub fn new(settings: BandwidthSettings) -> Self {
+        BandwidthEstimator {
+            settings,
+            rate: settings.budget as f64,
+            tokens: 0.0,
+            last_refill: None,
+            last_decrease: None,
+            in_flight: VecDeque::new(),
+            acking: false,
+            loss: 0.0,
+            stats: BandwidthStats::default(),
+        }
+    }
+
+    /// New limits from a settings reload, the current rate is kept within them
+    pub fn set_settings(&mut self, settings: BandwidthSettings) {
+        self.settings = settings;
+        self.rate = self
+            .rate
+            .clamp(settings.min_rate as f64, settings.budget as f64);
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate as u32
+    }
+
+    /// Smoothed fraction of snapshots lost
+    pub fn loss(&self) -> f64 {
+        self.loss
+    }
+
+    pub fn stats(&self) -> BandwidthStats {
+        self.stats
+    }
+
+    /// Note a snapshot about to be built and return how many bytes it may use
+    pub fn next_snapshot(&mut self, tick: u32, now: Instant) -> usize {
+        self.refill(now);
+        self.expire(now);
+
+        self.in_flight.push_back(SentSnapshot {
+            tick,
+            bytes: 0,
+            sent_at: now,
+        });
+
+        self.tokens.max(0.0) as usize
+    }
+
+    /// Whatever was sent goes against the budget, even if it overshoots it
+    pub fn on_sent(&mut self, bytes: usize) {
+        self.tokens -= bytes as f64;
+        self.stats.bytes_sent += bytes as u64;
+
+        if let Some(last) = self.in_flight.back_mut() {
+            last.bytes += bytes;
+        }
+    }
+
+    /// The client got snapshot `tick`. Anything older still waiting never arrived.
+    pub fn on_ack(&mut self, tick: u32, now: Instant) {
+        self.acking = true;
+
+        let (mut delivered, mut lost) = (0, 0);
+        while let Some(sent) = self.in_flight.front() {
+            // Wrapping compare so a tick counter rolling over is still "older"
+            if tick.wrapping_sub(sent.tick) > u32::MAX / 2 {
+                break;
+            }
+
+            if sent.tick == tick {
+                delivered += 1;
+                self.stats.bytes_acked += sent.bytes as u64;
+            } else {
+                lost += 1;
+            }
+            self.in_flight.pop_front();
+        }
+
+        // A duplicate or reordered ack tells us nothing new
+        if delivered + lost > 0 {
+            self.adjust(delivered, lost, now);
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let cap = self.rate * BURST.as_secs_f64();
+
+        // The first snapshot gets a full burst rather than nothing
+        let Some(last) = self.last_refill.replace(now) else {
+            self.tokens = cap;
+            return;
+        };
+
+        let elapsed = now.saturating_duration_since(last).as_secs_f64();
+        self.tokens = (self.tokens + self.rate * elapsed).min(cap);
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let mut lost = 0;
+
+        while let Some(sent) = self.in_flight.front() {
+            let timed_out = now.saturating_duration_since(sent.sent_at) > ACK_TIMEOUT;
+            if !timed_out && self.in_flight.len() < MAX_IN_FLIGHT {
+                break;
+            }
+
+            self.in_flight.pop_front();
+            lost += 1;
+        }
+
+        // A client that never acks has not lost anything, it just doesn't tell us
+        if self.acking && lost > 0 {
+            self.adjust(0, lost, now);
+        }
+    }
+
+    fn adjust(&mut self, delivered: u64, lost: u64, now: Instant) {
+        self.stats.snapshots_acked += delivered;
+        self.stats.snapshots_lost += lost;
+
+        let sample = lost as f64 / (delivered + lost) as f64;
+        self.loss = (self.loss * 7.0 + sample) / 8.0;
+
+        let cooled_down = self
+            .last_decrease
+            .is_none_or(|last| now.saturating_duration_since(last) >= DECREASE_COOLDOWN);
+
+        if lost > 0 && self.loss > LOSS_THRESHOLD {
+            if cooled_down {
+                self.rate = (self.rate * DECREASE_FACTOR).max(self.settings.min_rate as f64);
+                self.last_decrease = Some(now);
+            }
+        } else if lost == 0 {
+            self.rate =
+                (self.rate + ADDITIVE_INCREASE * delivered as f64).min(self.settings.budget as f64);
+        }
+    }
+}
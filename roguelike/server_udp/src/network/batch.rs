@@ -0,0 +1,97 @@
+use std::io;
+
+use crate::config::globals::commands::BATCH;
+
+/// Every message in a batch is prefixed with its length as a u16
+const LENGTH_PREFIX_SIZE: usize = 2;
+
+/// Pack queued messages into as few datagrams as fit under `mtu`, keeping their order.
+/// A datagram holding a single message is sent as the plain message, and messages too
+/// big for a batch go out alone to be fragmented.
+pub fn pack(messages: &[Vec<u8>], mtu: usize) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    let mut batch: Vec<&[u8]> = Vec::new();
+    let mut batch_size = 1;
+
+    for msg in messages.iter() {
+        let size = LENGTH_PREFIX_SIZE + msg.len();
+
+        if !batch.is_empty() && batch_size + size > mtu {
+            packets.push(finish(&batch));
+            batch.clear();
+            batch_size = 1;
+        }
+
+        if 1 + size > mtu || msg.len() > u16::MAX as usize {
+            packets.push(msg.clone());
+            continue;
+        }
+
+        batch.push(msg);
+        batch_size += size;
+    }
+
+    if !batch.is_empty() {
+        packets.push(finish(&batch));
+    }
+
+    packets
+}
+
+fn finish(batch: &[&[u8]]) -> Vec<u8> {
+    if let [msg] = batch {
+        return msg.to_vec();
+    }
+
+    let mut packet = vec![BATCH];
+    for msg in batch.iter() {
+        packet.extend_from_slice(&(msg.len() as u16).to_le_bytes());
+        packet.extend_from_slice(msg);
+    }
+
+    packet
+}
+
+/// Split a BATCH datagram back into its messages. Packets that are not batches come back
+/// as the only message.
+pub fn unpack(packet: &[u8]) -> Result<Vec<&[u8]>, io::Error> {
+    if packet.first() != Some(&BATCH) {
+        return Ok(vec![packet]);
+    }
+
+    let mut messages = Vec::new();
+    let mut offset = 1;
+
+    while offset < packet.len() {
+        if packet.len() < offset + LENGTH_PREFIX_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated length in BATCH",
+            ));
+        }
+
+        let length = u16::from_le_bytes([packet[offset], packet[offset + 1]]) as usize;
+        offset += LENGTH_PREFIX_SIZE;
+
+        if length == 0 || packet.len() < offset + length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Message in BATCH is empty or too short",
+            ));
+        }
+
+        // Batches and fragments only ever wrap whole messages
+        let msg = &packet[offset..offset + length];
+        if msg[0] == BATCH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Nested BATCH is not allowed",
+            ));
+        }
+
+        messages.push(msg);
+        offset += length;
+    }
+
+    Ok(messages)
+}
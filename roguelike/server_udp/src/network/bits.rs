@@ -0,0 +1,210 @@
+use std::f32::consts::TAU;
+
+use super::codec::CodecError;
+
+/// A float squeezed into `bits` bits over `min..=max`. Values outside the range are clamped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantized {
+    pub min: f32,
+    pub max: f32,
+    pub bits: u32,
+}
+
+impl Quantized {
+    pub const fn new(min: f32, max: f32, bits: u32) -> Self {
+        Quantized { min, max, bits }
+    }
+
+    /// The value a reader gets back after `value` goes over the wire
+    pub fn snap(&self, value: f32) -> f32 {
+        self.decode(self.encode(value))
+    }
+
+    fn steps(&self) -> u32 {
+        ((1u64 << self.bits) - 1) as u32
+    }
+
+    fn encode(&self, value: f32) -> u32 {
+        // NaN ends up at min rather than anywhere surprising
+        let value = if value.is_nan() { self.min } else { value };
+        let t = (value.clamp(self.min, self.max) - self.min) / (self.max - self.min);
+        (t * self.steps() as f32).round() as u32
+    }
+
+    fn decode(&self, raw: u32) -> f32 {
+        self.min + raw as f32 / self.steps() as f32 * (self.max - self.min)
+    }
+}
+
+/// Bits needed to store every value in `0..=max`
+pub fn bits_for(max: u32) -> u32 {
+    u32::BITS - max.leading_zeros()
+}
+
+/// Writes values bit by bit, least significant bit first
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    scratch: u64,
+    scratch_bits: u32,
+}
+
+impl BitWriter {
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        debug_assert!(count <= 32);
+        if count == 0 {
+            return;
+        }
+
+        let mask = (1u64 << count) - 1;
+        self.scratch |= (value as u64 & mask) << self.scratch_bits;
+        self.scratch_bits += count;
+
+        while self.scratch_bits >= 8 {
+            self.bytes.push(self.scratch as u8);
+            self.scratch >>= 8;
+            self.scratch_bits -= 8;
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_bits(value as u32, 1);
+    }
+
+    /// 7 bits per group, small numbers take one byte
+    pub fn write_varint(&mut self, mut value: u32) {
+        loop {
+            let group = value & 0x7f;
+            value >>= 7;
+
+            if value == 0 {
+                self.write_bits(group, 8);
+                return;
+            }
+            self.write_bits(group | 0x80, 8);
+        }
+    }
+
+    /// Zigzag first so small negative numbers stay small
+    pub fn write_signed_varint(&mut self, value: i32) {
+        self.write_varint(((value << 1) ^ (value >> 31)) as u32);
+    }
+
+    /// Integer known to sit in `min..=max`, clamped if it does not
+    pub fn write_bounded(&mut self, value: u32, min: u32, max: u32) {
+        let value = value.clamp(min, max) - min;
+        self.write_bits(value, bits_for(max - min));
+    }
+
+    pub fn write_quantized(&mut self, value: f32, quantized: Quantized) {
+        self.write_bits(quantized.encode(value), quantized.bits);
+    }
+
+    /// Angle in radians, any value wraps into one turn
+    pub fn write_angle(&mut self, radians: f32, bits: u32) {
+        let turns = if radians.is_finite() {
+            (radians / TAU).rem_euclid(1.0)
+        } else {
+            0.0
+        };
+        let steps = 1u64 << bits;
+        let raw = ((turns as f64 * steps as f64).round() as u64 % steps) as u32;
+        self.write_bits(raw, bits);
+    }
+
+    /// Pad the last byte with zeros and hand back the buffer
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.scratch_bits > 0 {
+            self.bytes.push(self.scratch as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Reads what a `BitWriter` wrote. Running past the end is an error, never a panic.
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, position: 0 }
+    }
+
+    /// Bytes touched so far, counting a partly read last byte as read
+    pub fn bytes_read(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> Result<u32, CodecError> {
+        debug_assert!(count <= 32);
+        let end = self.position + count as usize;
+        if end > self.bytes.len() * 8 {
+            return Err(CodecError::UnexpectedEnd {
+                needed: end.div_ceil(8) - self.position / 8,
+                remaining: self.bytes.len() - self.position / 8,
+            });
+        }
+
+        let mut value = 0u64;
+        for i in 0..count as usize {
+            let bit = self.position + i;
+            let set = (self.bytes[bit / 8] >> (bit % 8)) & 1;
+            value |= (set as u64) << i;
+        }
+        self.position += count as usize;
+
+        Ok(value as u32)
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, CodecError> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    pub fn read_varint(&mut self) -> Result<u32, CodecError> {
+        let mut value = 0u32;
+
+        for group in 0..5 {
+            let byte = self.read_bits(8)?;
+            let bits = byte & 0x7f;
+
+            // The fifth group only has room for the top 4 bits of a u32
+            if group == 4 && (bits > 0x0f || byte & 0x80 != 0) {
+                break;
+            }
+
+            value |= bits << (group * 7);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(CodecError::InvalidValue("varint"))
+    }
+
+    pub fn read_signed_varint(&mut self) -> Result<i32, CodecError> {
+        let raw = self.read_varint()?;
+        Ok(((raw >> 1) as i32) ^ -((raw & 1) as i32))
+    }
+
+    pub fn read_bounded(&mut self, min: u32, max: u32) -> Result<u32, CodecError> {
+        let value = self.read_bits(bits_for(max - min))?;
+        if value > max - min {
+            return Err(CodecError::InvalidValue("bounded integer"));
+        }
+
+        Ok(min + value)
+    }
+
+    pub fn read_quantized(&mut self, quantized: Quantized) -> Result<f32, CodecError> {
+        Ok(quantized.decode(self.read_bits(quantized.bits)?))
+    }
+
+    /// Comes back in `0..TAU`
+    pub fn read_angle(&mut self, bits: u32) -> Result<f32, CodecError> {
+        let raw = self.read_bits(bits)?;
+        Ok(raw as f32 / (1u64 << bits) as f32 * TAU)
+    }
+}
@@ -0,0 +1,177 @@
+use std::{error::Error, fmt, io};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CodecError {
+    /// Needed `needed` more bytes but only `remaining` were left
+    UnexpectedEnd {
+        needed: usize,
+        remaining: usize,
+    },
+    /// Bytes left over after the whole message was read
+    TrailingBytes(usize),
+    InvalidUtf8,
+    /// A command byte or enum tag we don't know
+    UnknownTag {
+        what: &'static str,
+        tag: u8,
+    },
+    /// The field decoded but holds a value it can never have
+    InvalidValue(&'static str),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::UnexpectedEnd { needed, remaining } => {
+                write!(
+                    f,
+                    "Packet too short: needed {needed} more bytes, {remaining} left"
+                )
+            }
+            CodecError::TrailingBytes(count) => write!(f, "{count} unexpected bytes at the end"),
+            CodecError::InvalidUtf8 => write!(f, "String is not valid UTF-8"),
+            CodecError::UnknownTag { what, tag } => write!(f, "Unknown {what} {tag}"),
+            CodecError::InvalidValue(what) => write!(f, "Invalid {what}"),
+        }
+    }
+}
+
+impl Error for CodecError {}
+
+impl From<CodecError> for io::Error {
+    fn from(e: CodecError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Cursor over a received packet. Every read checks the length first.
+#[derive(Debug)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], CodecError> {
+        if count > self.remaining() {
+            return Err(CodecError::UnexpectedEnd {
+                needed: count,
+                remaining: self.remaining(),
+            });
+        }
+
+        let bytes = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn take_array<const N: usize>(&mut self) -> Result<[u8; N], CodecError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    /// Everything not read yet, without consuming it
+    pub fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+
+    /// Error out if the message did not use up the whole packet
+    pub fn finish(self) -> Result<(), CodecError> {
+        match self.remaining() {
+            0 => Ok(()),
+            count => Err(CodecError::TrailingBytes(count)),
+        }
+    }
+}
+
+/// A value with one wire layout, used for both directions so they can't drift apart
+pub trait Codec: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError>;
+}
+
+macro_rules! le_codec {
+    ($($ty:ty),+) => {
+        $(
+            impl Codec for $ty {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+
+                fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+                    Ok(<$ty>::from_le_bytes(reader.take_array()?))
+                }
+            }
+        )+
+    };
+}
+
+le_codec!(u8, u16, u32, u64, i32, f32);
+
+impl Codec for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        match u8::decode(reader)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(CodecError::UnknownTag { what: "bool", tag }),
+        }
+    }
+}
+
+/// u16 byte length, then UTF-8. Anything past u16::MAX bytes is cut at a char boundary.
+impl Codec for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut length = self.len().min(u16::MAX as usize);
+        while !self.is_char_boundary(length) {
+            length -= 1;
+        }
+
+        (length as u16).encode(out);
+        out.extend_from_slice(&self.as_bytes()[..length]);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let length = u16::decode(reader)? as usize;
+        let bytes = reader.take(length)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| CodecError::InvalidUtf8)
+    }
+}
+
+/// u16 element count, then the elements. Anything past u16::MAX elements is dropped.
+impl<T: Codec> Codec for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let count = self.len().min(u16::MAX as usize);
+        (count as u16).encode(out);
+        for item in self.iter().take(count) {
+            item.encode(out);
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let count = u16::decode(reader)? as usize;
+
+        // The count comes off the wire, so it doesn't get to size the allocation
+        let mut items = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            items.push(T::decode(reader)?);
+        }
+        Ok(items)
+    }
+}
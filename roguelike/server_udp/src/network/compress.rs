@@ -0,0 +1,258 @@
+use std::{error::Error, fmt, fs, io};
+
+use zstd::{
+    dict::{DecoderDictionary, EncoderDictionary},
+    zstd_safe::{CParameter, get_dict_id_from_dict},
+};
+
+use crate::config::{globals::commands::COMPRESSED, settings::CompressionSettings};
+
+/// [COMPRESSED, algorithm u8, original length u16]
+pub const COMPRESSED_HEADER_SIZE: usize = 4;
+
+/// How a message body is compressed. The wire tag doubles as the algorithm's bit in a
+/// `CompressionOffer`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+    /// Zstd with the dictionary both sides were shipped with
+    ZstdDictionary,
+}
+
+impl Compression {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd),
+            3 => Some(Compression::ZstdDictionary),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd => 2,
+            Compression::ZstdDictionary => 3,
+        }
+    }
+}
+
+/// What a client can decompress, sent with its handshake
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CompressionOffer {
+    /// One bit per `Compression` tag
+    pub algorithms: u8,
+    /// Id of the zstd dictionary the client has, 0 for none
+    pub dictionary_id: u32,
+}
+
+impl CompressionOffer {
+    pub fn supports(&self, compression: Compression) -> bool {
+        self.algorithms & (1 << compression.as_u8()) != 0
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CompressionError {
+    Malformed,
+    /// Algorithm tag we don't know or weren't set up for
+    Unsupported(u8),
+    /// The body did not decompress to the length in the header
+    Corrupt,
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::Malformed => write!(f, "Malformed compressed message"),
+            CompressionError::Unsupported(tag) => write!(f, "Unsupported compression {tag}"),
+            CompressionError::Corrupt => write!(f, "Compressed message is corrupt"),
+        }
+    }
+}
+
+impl Error for CompressionError {}
+
+/// Bytes in and out of the compressor for one client
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CompressionStats {
+    pub messages: u64,
+    /// How many of `messages` went out smaller than they came in
+    pub compressed: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl CompressionStats {
+    pub fn record(&mut self, bytes_in: usize, bytes_out: usize) {
+        self.messages += 1;
+        if bytes_out < bytes_in {
+            self.compressed += 1;
+        }
+        self.bytes_in += bytes_in as u64;
+        self.bytes_out += bytes_out as u64;
+    }
+
+    /// Bytes sent per byte we had to send, 1.0 until something shrinks
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 1.0;
+        }
+        self.bytes_out as f64 / self.bytes_in as f64
+    }
+}
+
+struct Dictionary {
+    id: u32,
+    encoder: EncoderDictionary<'static>,
+    decoder: DecoderDictionary<'static>,
+}
+
+/// Compresses whole serialized messages, one at a time so each can still be batched and
+/// decoded on its own
+pub struct Compressor {
+    settings: CompressionSettings,
+    dictionary: Option<Dictionary>,
+}
+
+impl Compressor {
+    /// Loads the dictionary named in the settings, if any
+    pub fn new(settings: CompressionSettings) -> io::Result<Self> {
+        let dictionary = match &settings.dictionary {
+            Some(path) => {
+                let bytes = fs::read(path)?;
+                let id = get_dict_id_from_dict(&bytes).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is not a zstd dictionary", path.display()),
+                    )
+                })?;
+
+                Some(Dictionary {
+                    id: id.get(),
+                    encoder: EncoderDictionary::try_copy(&bytes, settings.zstd_level)?,
+                    decoder: DecoderDictionary::try_copy(&bytes)?,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Compressor {
+            settings,
+            dictionary,
+        })
+    }
+
+    /// Id a client must offer to get dictionary compression, 0 without a dictionary
+    pub fn dictionary_id(&self) -> u32 {
+        self.dictionary
+            .as_ref()
+            .map_or(0, |dictionary| dictionary.id)
+    }
+
+    /// Pick the best algorithm both sides have
+    pub fn negotiate(&self, offer: CompressionOffer) -> Compression {
+        if !self.settings.enabled {
+            return Compression::None;
+        }
+
+        if offer.supports(Compression::ZstdDictionary)
+            && self.dictionary.is_some()
+            && offer.dictionary_id == self.dictionary_id()
+        {
+            Compression::ZstdDictionary
+        } else if offer.supports(Compression::Zstd) {
+            Compression::Zstd
+        } else if offer.supports(Compression::Lz4) {
+            Compression::Lz4
+        } else {
+            Compression::None
+        }
+    }
+
+    /// A COMPRESSED message, or None when the message is small or does not shrink
+    pub fn compress(&self, message: &[u8], compression: Compression) -> Option<Vec<u8>> {
+        if message.len() < self.settings.threshold || message.len() > u16::MAX as usize {
+            return None;
+        }
+
+        let body = match compression {
+            Compression::None => return None,
+            Compression::Lz4 => lz4_flex::block::compress(message),
+            Compression::Zstd => self.zstd(message, None).ok()?,
+            Compression::ZstdDictionary => self
+                .zstd(message, Some(&self.dictionary.as_ref()?.encoder))
+                .ok()?,
+        };
+
+        if COMPRESSED_HEADER_SIZE + body.len() >= message.len() {
+            return None;
+        }
+
+        let mut packet = Vec::with_capacity(COMPRESSED_HEADER_SIZE + body.len());
+        packet.push(COMPRESSED);
+        packet.push(compression.as_u8());
+        packet.extend_from_slice(&(message.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&body);
+        Some(packet)
+    }
+
+    /// The original message back out of a COMPRESSED one. Never allocates more than the
+    /// length in the header, which is at most u16::MAX.
+    pub fn decompress(&self, packet: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        if packet.len() < COMPRESSED_HEADER_SIZE || packet[0] != COMPRESSED {
+            return Err(CompressionError::Malformed);
+        }
+
+        let tag = packet[1];
+        let length = u16::from_le_bytes([packet[2], packet[3]]) as usize;
+        let body = &packet[COMPRESSED_HEADER_SIZE..];
+        if length == 0 {
+            return Err(CompressionError::Malformed);
+        }
+
+        let message = match Compression::from_u8(tag) {
+            Some(Compression::Lz4) => lz4_flex::block::decompress(body, length).ok(),
+            Some(Compression::Zstd) => zstd::bulk::Decompressor::new()
+                .and_then(|mut zstd| zstd.decompress(body, length))
+                .ok(),
+            Some(Compression::ZstdDictionary) => {
+                let dictionary = self
+                    .dictionary
+                    .as_ref()
+                    .ok_or(CompressionError::Unsupported(tag))?;
+                zstd::bulk::Decompressor::with_prepared_dictionary(&dictionary.decoder)
+                    .and_then(|mut zstd| zstd.decompress(body, length))
+                    .ok()
+            }
+            Some(Compression::None) | None => return Err(CompressionError::Unsupported(tag)),
+        };
+
+        match message {
+            Some(message) if message.len() == length => Ok(message),
+            _ => Err(CompressionError::Corrupt),
+        }
+    }
+
+    // The header already carries the length and the dictionary was negotiated, so the
+    // frame leaves both out
+    fn zstd(
+        &self,
+        message: &[u8],
+        dictionary: Option<&EncoderDictionary<'static>>,
+    ) -> io::Result<Vec<u8>> {
+        let mut zstd = match dictionary {
+            Some(dictionary) => zstd::bulk::Compressor::with_prepared_dictionary(dictionary)?,
+            None => zstd::bulk::Compressor::new(self.settings.zstd_level)?,
+        };
+        zstd.set_parameter(CParameter::ContentSizeFlag(false))?;
+        zstd.set_parameter(CParameter::DictIdFlag(false))?;
+        zstd.compress(message)
+    }
+}
@@ -0,0 +1,233 @@
+use std::{collections::HashMap, error::Error, fmt, net::SocketAddr, time::Instant};
+
+use crate::config::{globals::commands::FRAGMENT, settings::FragmentSettings};
+
+/// [FRAGMENT, message id u16, index u8, count u8]
+pub const FRAGMENT_HEADER_SIZE: usize = 5;
+
+pub type FragmentedMessageId = u16;
+
+#[derive(Debug, PartialEq)]
+pub enum FragmentError {
+    Malformed,
+    /// Fragment count disagrees with earlier fragments of the same message
+    Inconsistent(FragmentedMessageId),
+    TooLarge(usize),
+    /// The sender already has too much half-assembled data with us
+    MemoryLimit,
+}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::Malformed => write!(f, "Malformed fragment"),
+            FragmentError::Inconsistent(id) => {
+                write!(f, "Fragments of message {id} disagree on their count")
+            }
+            FragmentError::TooLarge(size) => {
+                write!(f, "Fragmented message of {size} bytes is too large")
+            }
+            FragmentError::MemoryLimit => write!(f, "Reassembly memory limit reached"),
+        }
+    }
+}
+
+impl Error for FragmentError {}
+
+/// Split a serialized message into FRAGMENT packets no bigger than `mtu`. Messages that
+/// already fit go out untouched.
+pub fn split(
+    message: &[u8],
+    mtu: usize,
+    message_id: FragmentedMessageId,
+) -> Result<Vec<Vec<u8>>, FragmentError> {
+    if message.len() <= mtu {
+        return Ok(vec![message.to_vec()]);
+    }
+
+    let chunk_size = mtu.saturating_sub(FRAGMENT_HEADER_SIZE);
+    if chunk_size == 0 {
+        return Err(FragmentError::Malformed);
+    }
+
+    let count = message.len().div_ceil(chunk_size);
+    if count > u8::MAX as usize {
+        return Err(FragmentError::TooLarge(message.len()));
+    }
+
+    Ok(message
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut packet = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+            packet.push(FRAGMENT);
+            packet.extend_from_slice(&message_id.to_le_bytes());
+            packet.push(index as u8);
+            packet.push(count as u8);
+            packet.extend_from_slice(chunk);
+            packet
+        })
+        .collect())
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    started_at: Instant,
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+}
+
+/// Collects FRAGMENT packets per sender until a message is complete. Sets that time out
+/// or would go over the memory limits are thrown away.
+#[derive(Debug)]
+pub struct Reassembler {
+    settings: FragmentSettings,
+    partial: HashMap<(SocketAddr, FragmentedMessageId), PartialMessage>,
+    bytes_by_sender: HashMap<SocketAddr, usize>,
+    total_bytes: usize,
+}
+
+impl Reassembler {
+    pub fn new(settings: FragmentSettings) -> Self {
+        Reassembler {
+            settings,
+            partial: HashMap::new(),
+            bytes_by_sender: HashMap::new(),
+            total_bytes: 0,
+        }
+    }
+
+    /// New limits from a settings reload, messages already in progress keep going
+    pub fn set_settings(&mut self, settings: FragmentSettings) {
+        self.settings = settings;
+    }
+
+    /// Store one FRAGMENT packet. Returns the whole message once its last fragment arrives.
+    pub fn receive(
+        &mut self,
+        sender: SocketAddr,
+        packet: &[u8],
+        now: Instant,
+    ) -> Result<Option<Vec<u8>>, FragmentError> {
+        if packet.len() <= FRAGMENT_HEADER_SIZE || packet[0] != FRAGMENT {
+            return Err(FragmentError::Malformed);
+        }
+
+        let message_id = u16::from_le_bytes([packet[1], packet[2]]);
+        let index = packet[3] as usize;
+        let count = packet[4] as usize;
+        let payload = &packet[FRAGMENT_HEADER_SIZE..];
+
+        if count < 2 || index >= count {
+            return Err(FragmentError::Malformed);
+        }
+
+        let key = (sender, message_id);
+
+        // A set that ran out of time starts over, even if expire has not got to it yet
+        let timeout = self.settings.timeout;
+        if self
+            .partial
+            .get(&key)
+            .is_some_and(|partial| now.duration_since(partial.started_at) > timeout)
+        {
+            self.discard(key);
+        }
+
+        let partial = self.partial.entry(key).or_insert_with(|| PartialMessage {
+            started_at: now,
+            fragments: vec![None; count],
+            received: 0,
+            bytes: 0,
+        });
+
+        if partial.fragments.len() != count {
+            self.discard(key);
+            return Err(FragmentError::Inconsistent(message_id));
+        }
+
+        // Resent fragment
+        if partial.fragments[index].is_some() {
+            return Ok(None);
+        }
+
+        if partial.bytes + payload.len() > self.settings.max_message_size {
+            let size = partial.bytes + payload.len();
+            self.discard(key);
+            return Err(FragmentError::TooLarge(size));
+        }
+
+        let sender_bytes = self.bytes_by_sender.get(&sender).copied().unwrap_or(0);
+        if sender_bytes + payload.len() > self.settings.max_pending_bytes_per_client
+            || self.total_bytes + payload.len() > self.settings.max_pending_bytes
+        {
+            self.discard(key);
+            return Err(FragmentError::MemoryLimit);
+        }
+
+        partial.fragments[index] = Some(payload.to_vec());
+        partial.received += 1;
+        partial.bytes += payload.len();
+        *self.bytes_by_sender.entry(sender).or_default() += payload.len();
+        self.total_bytes += payload.len();
+
+        if partial.received < count {
+            return Ok(None);
+        }
+
+        let partial = self.discard(key).expect("message was just completed");
+        Ok(Some(
+            partial.fragments.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    /// Drop every set that has been waiting longer than the timeout. Goes over all of
+    /// them, so it runs on a timer rather than per fragment.
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.settings.timeout;
+        let expired: Vec<_> = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| now.duration_since(partial.started_at) > timeout)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for key in expired {
+            self.discard(key);
+        }
+    }
+
+    /// Forget everything from a sender that left
+    pub fn forget(&mut self, sender: SocketAddr) {
+        let keys: Vec<_> = self
+            .partial
+            .keys()
+            .filter(|(addr, _)| *addr == sender)
+            .copied()
+            .collect();
+
+        for key in keys {
+            self.discard(key);
+        }
+    }
+
+    /// Bytes held in half-assembled messages
+    pub fn pending_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    fn discard(&mut self, key: (SocketAddr, FragmentedMessageId)) -> Option<PartialMessage> {
+        let partial = self.partial.remove(&key)?;
+
+        self.total_bytes -= partial.bytes;
+        if let Some(bytes) = self.bytes_by_sender.get_mut(&key.0) {
+            *bytes -= partial.bytes;
+            if *bytes == 0 {
+                self.bytes_by_sender.remove(&key.0);
+            }
+        }
+
+        Some(partial)
+    }
+}
@@ -1,234 +1,720 @@
-use std::{io, sync::atomic::AtomicBool, u16, usize};
-
-use cgmath::num_traits::ToBytes;
+use tracing::trace;
 
 use crate::{
     config::globals::{
         self,
-        commands::{ACK, CREATE_ROOM, ERROR, HANDSHAKE, JOIN_ROOM, LEAVE, PING},
+        commands::{
+            ACK, ACTOR_DESPAWN, ACTOR_SPAWN, CREATE_ROOM, DROP_ITEM, ENTITY_DESPAWN, ENTITY_SPAWN,
+            EQUIP, ERROR, FLOOR_CHANGED, HANDSHAKE, INTERACT, INVENTORY, JOIN_ROOM, LEAVE, MOTD,
+            PING, PLAYER_INPUT, PLAYER_STATE, PONG, ROOM_SNAPSHOT, RUN_ENDED, SERVER_SHUTDOWN,
+            SNAPSHOT_ACK, USE_ITEM, VOTE_DESCEND,
+        },
     },
     game::{
-        player::{PlayerID, PlayerName},
-        room::{RoomId, RoomName, RoomPass},
+        Position,
+        entity::{Entity, EntityId, EntityKind},
+        input::{InputAction, InputCommand},
+        interest::ActorId,
+        inventory::{INVENTORY_SLOTS, Inventory, ItemStack},
+        map::{MAP_HEIGHT, MAP_WIDTH, TILE_SIZE},
+        mode::GameMode,
+        player::{InputSeq, LeaveReason, LifeState, PLAYER_SPEED, PlayerID, PlayerName},
+        room::{RoomEvent, RoomId, RoomName, RoomPass, RunOutcome},
+        snapshot::{EnemySnapshot, PlayerSnapshot, RoomSnapshot},
+    },
+    logging,
+    network::{
+        bits::{BitReader, BitWriter, Quantized},
+        codec::{Codec, CodecError, Reader},
+        compress::{Compression, CompressionOffer},
     },
 };
 
-#[derive(Debug, PartialEq)]
-pub enum InputAction {
-    Move(f32, f32),
-    Shoot(f32, f32),
-}
-
-#[derive(Debug, PartialEq)]
+const ENTITY_KIND_PICKUP: u8 = 0;
+const ENTITY_KIND_CHEST: u8 = 1;
+const ENTITY_KIND_STAIRS: u8 = 2;
+
+const INPUT_MOVE: u8 = 0;
+const INPUT_SHOOT: u8 = 1;
+
+const LIFE_ALIVE: u8 = 0;
+const LIFE_DOWNED: u8 = 1;
+const LIFE_DEAD: u8 = 2;
+
+const OUTCOME_DEFEAT: u8 = 0;
+const OUTCOME_INTERRUPTED: u8 = 1;
+
+const ACTOR_PLAYER: u8 = 0;
+const ACTOR_ENEMY: u8 = 1;
+
+/// Whole map at 1/64 of a pixel
+const POSITION: Quantized = Quantized::new(0.0, WORLD_SIZE, 16);
+const VELOCITY: Quantized = Quantized::new(-PLAYER_SPEED, PLAYER_SPEED, 12);
+/// Downed timers in seconds, about 1/16s apart
+const DOWNED_TIMER: Quantized = Quantized::new(0.0, 64.0, 10);
+/// How far the stick is pushed. Anything past 1 moves at full speed anyway.
+const MOVE_MAGNITUDE: Quantized = Quantized::new(0.0, 1.0, 6);
+const MOVE_ANGLE_BITS: u32 = 10;
+/// Aim only needs a direction, the room normalizes it anyway
+const SHOOT_ANGLE_BITS: u32 = 12;
+
+const WORLD_SIZE: f32 = TILE_SIZE
+    * if MAP_WIDTH > MAP_HEIGHT {
+        MAP_WIDTH
+    } else {
+        MAP_HEIGHT
+    } as f32;
+
+/// Marks "nothing equipped" in the INVENTORY packet
+const NO_EQUIPPED_SLOT: u8 = u8::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Message {
     Error(String),
 
-    /// Client/Server healthcheck
-    Ping,
+    /// Client/Server healthcheck with a sequence id and the sender's clock in ms.
+    /// A bare PING byte from older clients reads as Ping(0, 0).
+    Ping(u32, u64),
+
+    /// Answer to a Ping: sequence id, the ping's timestamp echoed back, then the sender's
+    /// clock in ms and tick. Clients put 0 in the tick.
+    Pong(u32, u64, u64, u32),
 
-    /// Handshake on connect
-    Handshake(PlayerName),
+    /// Handshake on connect, with the compression the client can decode. Older clients
+    /// leave the offer out and get uncompressed messages.
+    Handshake(PlayerName, CompressionOffer),
 
-    /// Server acknowledge handshake with PlayerId
-    Ack(PlayerID),
+    /// Server acknowledge handshake with PlayerId and the compression it picked
+    Ack(PlayerID, Compression),
 
-    /// Create new room/match
-    CreateRoom(RoomName, RoomPass),
+    /// Create new room/match. Older clients leave out the mode and get Standard.
+    CreateRoom(RoomName, RoomPass, GameMode),
 
-    /// Leaves room/match
-    Leave(PlayerID),
+    /// Leaves room/match. Clients leave out the reason and get Quit, the server fills it in
+    /// when it tells peers.
+    Leave(PlayerID, LeaveReason),
 
     /// Join room/match
     JoinRoom(RoomId, RoomPass),
-    ///// Client sends input
-    //PlayerInput(PlayerID, InputAction),
-    //
-    ///// Server sends full room state
-    //RoomSnapshot(Room),
+
+    /// Client sends its newest input plus the few before it, so one lost datagram
+    /// doesn't lose any movement. Oldest first.
+    PlayerInput(PlayerID, Vec<InputCommand>),
+
+    /// Client uses the item in an inventory slot
+    UseItem(u8),
+
+    /// Client drops `count` items from an inventory slot onto the map
+    DropItem(u8, u16),
+
+    /// Client equips the weapon in an inventory slot
+    Equip(u8),
+
+    /// Client picks up a pickup or opens a chest
+    Interact(EntityId),
+
+    /// Server sends the full inventory, only ever to its owner
+    Inventory(Inventory),
+
+    /// Server announces a new or changed entity to the room
+    EntitySpawn(Entity),
+
+    /// Server removes an entity from the room
+    EntityDespawn(EntityId),
+
+    /// Server moved the party to a new floor: depth, seed, elapsed run time in ms, kills
+    FloorChanged(u32, u64, u64, u32),
+
+    /// Client votes to descend without waiting for everyone to reach the stairs
+    VoteDescend,
+
+    /// Server tells the room a player was hurt, downed, revived or died
+    PlayerState(PlayerID, LifeState, i32),
+
+    /// Server ends the run: outcome, depth reached, elapsed run time in ms, kills
+    RunEnded(RunOutcome, u32, u64, u32),
+
+    /// Server sends the part of the room state this client is allowed to see
+    RoomSnapshot(RoomSnapshot),
+
+    /// A player or enemy came into this client's view
+    ActorSpawn(ActorId),
+
+    /// A player or enemy left this client's view
+    ActorDespawn(ActorId),
+
+    /// Client got the snapshot for this tick. Sent for every snapshot that arrives, the
+    /// gaps are how the server sees loss.
+    SnapshotAck(u32),
+
+    /// Server is stopping: the reason and seconds left before it does, 0 once it has
+    ServerShutdown(String, u16),
+
+    /// Server's message of the day, sent after the handshake and whenever it changes
+    Motd(String),
 }
 
 impl Message {
     pub fn serialize(&self) -> Vec<u8> {
-        match self {
-            Message::Error(error_msg) => {
-                let mut packet = vec![globals::commands::ERROR];
-                let error_bytes = error_msg.as_bytes();
+        let mut packet = Vec::new();
+        self.encode(&mut packet);
+        packet
+    }
 
-                let length = error_bytes.len() as u16;
-                packet.extend_from_slice(&length.to_le_bytes());
-                packet.extend_from_slice(error_bytes);
+    pub fn deserialize(packet: &[u8]) -> Result<Message, CodecError> {
+        if packet.is_empty() {
+            return Err(CodecError::UnexpectedEnd {
+                needed: 1,
+                remaining: 0,
+            });
+        }
 
-                packet
-            }
+        trace!(target: logging::PACKETS, ?packet, "Deserializing packet");
 
-            Message::Ping => vec![PING],
-            Message::Handshake(player_name) => {
-                let mut packet = vec![HANDSHAKE];
-                let name_bytes = player_name.as_bytes();
-                let length = name_bytes.len() as u16;
+        // Older clients answer a ping with the bare command byte
+        if packet == [PING] {
+            return Ok(Message::Ping(0, 0));
+        }
 
-                // Add name length
-                packet.extend_from_slice(&length.to_le_bytes());
+        let mut reader = Reader::new(packet);
+        let message = Message::decode(&mut reader)?;
+        reader.finish()?;
 
-                // Add name
-                packet.extend_from_slice(name_bytes);
+        Ok(message)
+    }
+}
 
-                packet
+/// Lists every variant once with its command byte. Both directions are generated from the
+/// same field list, each field going through its own `Codec`.
+macro_rules! message_codec {
+    ($($variant:ident $(($($field:ident),+))? = $command:path,)+) => {
+        impl Codec for Message {
+            fn encode(&self, out: &mut Vec<u8>) {
+                match self {
+                    $(
+                        Message::$variant $(($($field),+))? => {
+                            out.push($command);
+                            $($($field.encode(out);)+)?
+                        }
+                    )+
+                }
             }
 
-            Message::Ack(player_id) => {
-                let mut packet = vec![ACK];
-                packet.extend_from_slice(&player_id.to_le_bytes());
-                packet
-            }
-            Message::Leave(player_id) => {
-                let mut packet = vec![LEAVE];
-                packet.extend_from_slice(&player_id.to_le_bytes());
-                packet
+            fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+                match u8::decode(reader)? {
+                    $(
+                        $command => Ok(Message::$variant $((
+                            $({
+                                let $field = Codec::decode(reader)?;
+                                $field
+                            }),+
+                        ))?),
+                    )+
+                    tag => Err(CodecError::UnknownTag { what: "command", tag }),
+                }
             }
+        }
+    };
+}
+
+message_codec! {
+    Error(message) = ERROR,
+    Ping(seq, time_ms) = PING,
+    Pong(seq, echo_ms, time_ms, tick) = PONG,
+    Handshake(player_name, compression) = HANDSHAKE,
+    Ack(player_id, compression) = ACK,
+    CreateRoom(room_name, password, mode) = CREATE_ROOM,
+    Leave(player_id, reason) = LEAVE,
+    JoinRoom(room_id, password) = JOIN_ROOM,
+    PlayerInput(player_id, commands) = PLAYER_INPUT,
+    UseItem(slot) = USE_ITEM,
+    DropItem(slot, count) = DROP_ITEM,
+    Equip(slot) = EQUIP,
+    Interact(entity_id) = INTERACT,
+    Inventory(inventory) = INVENTORY,
+    EntitySpawn(entity) = ENTITY_SPAWN,
+    EntityDespawn(entity_id) = ENTITY_DESPAWN,
+    FloorChanged(depth, seed, elapsed_ms, kills) = FLOOR_CHANGED,
+    VoteDescend = VOTE_DESCEND,
+    PlayerState(player_id, life, health) = PLAYER_STATE,
+    RunEnded(outcome, depth, elapsed_ms, kills) = RUN_ENDED,
+    RoomSnapshot(snapshot) = ROOM_SNAPSHOT,
+    ActorSpawn(actor) = ACTOR_SPAWN,
+    ActorDespawn(actor) = ACTOR_DESPAWN,
+    SnapshotAck(tick) = SNAPSHOT_ACK,
+    ServerShutdown(reason, countdown_secs) = SERVER_SHUTDOWN,
+    Motd(text) = MOTD,
+}
+
+impl From<RoomEvent> for Message {
+    fn from(event: RoomEvent) -> Self {
+        match event {
+            RoomEvent::FloorChanged {
+                depth,
+                seed,
+                elapsed_ms,
+                kills,
+            } => Message::FloorChanged(depth, seed, elapsed_ms, kills),
+            RoomEvent::PlayerStateChanged {
+                player_id,
+                life,
+                health,
+            } => Message::PlayerState(player_id, life, health),
+            RoomEvent::RunEnded {
+                outcome,
+                depth,
+                elapsed_ms,
+                kills,
+            } => Message::RunEnded(outcome, depth, elapsed_ms, kills),
+        }
+    }
+}
+
+////////////////////////////////////////////////
+
+impl Codec for Position {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.x.encode(out);
+        self.y.encode(out);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(Position::new(f32::decode(reader)?, f32::decode(reader)?))
+    }
+}
+
+impl Codec for ItemStack {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.item.encode(out);
+        self.count.encode(out);
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        Ok(ItemStack::new(u16::decode(reader)?, u16::decode(reader)?))
+    }
+}
 
-            Message::CreateRoom(room_name, password) => {
-                let mut packet = vec![CREATE_ROOM];
-                let name_bytes = room_name.as_bytes();
-                let pass_bytes = password.as_bytes();
+/// Equipped slot, slot count, then every slot with empty ones sent as item 0
+impl Codec for Inventory {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.equipped.unwrap_or(NO_EQUIPPED_SLOT).encode(out);
+        (self.slots.len() as u8).encode(out);
+        for slot in self.slots.iter() {
+            slot.unwrap_or(ItemStack::new(0, 0)).encode(out);
+        }
+    }
 
-                packet.extend_from_slice(&(name_bytes.len() as u16).to_le_bytes());
-                packet.extend_from_slice(name_bytes);
-                packet.extend_from_slice(&(pass_bytes.len()).to_le_bytes());
-                packet.extend_from_slice(pass_bytes);
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let equipped = u8::decode(reader)?;
+        if u8::decode(reader)? as usize != INVENTORY_SLOTS {
+            return Err(CodecError::InvalidValue("inventory slot count"));
+        }
 
-                packet
+        let mut inventory = Inventory {
+            equipped: (equipped != NO_EQUIPPED_SLOT).then_some(equipped),
+            ..Default::default()
+        };
+        for slot in inventory.slots.iter_mut() {
+            let stack = ItemStack::decode(reader)?;
+            *slot = (stack.item != 0).then_some(stack);
+        }
+
+        Ok(inventory)
+    }
+}
+
+impl Codec for Entity {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.id.encode(out);
+        self.position.encode(out);
+
+        match &self.kind {
+            EntityKind::Pickup(stack) => {
+                ENTITY_KIND_PICKUP.encode(out);
+                stack.encode(out);
             }
-            Message::JoinRoom(room_id, password) => {
-                let mut packet = vec![JOIN_ROOM];
-                packet.extend_from_slice(&room_id.to_le_bytes());
-                let pass_bytes = password.as_bytes();
-                packet.extend_from_slice(&(pass_bytes.len() as u16).to_le_bytes());
-                packet.extend_from_slice(pass_bytes);
-                packet
+            EntityKind::Chest { opened, locked } => {
+                ENTITY_KIND_CHEST.encode(out);
+                opened.encode(out);
+                locked.encode(out);
             }
-            _ => {
-                vec![]
+            EntityKind::Stairs => ENTITY_KIND_STAIRS.encode(out),
+        }
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let id = EntityId::decode(reader)?;
+        let position = Position::decode(reader)?;
+
+        let kind = match u8::decode(reader)? {
+            ENTITY_KIND_PICKUP => EntityKind::Pickup(ItemStack::decode(reader)?),
+            ENTITY_KIND_CHEST => EntityKind::Chest {
+                opened: bool::decode(reader)?,
+                locked: bool::decode(reader)?,
+            },
+            ENTITY_KIND_STAIRS => EntityKind::Stairs,
+            tag => {
+                return Err(CodecError::UnknownTag {
+                    what: "entity kind",
+                    tag,
+                });
             }
+        };
+
+        Ok(Entity { id, kind, position })
+    }
+}
+
+/// Tag, then both timers only when downed
+impl Codec for LifeState {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_u8().encode(out);
+
+        if let LifeState::Downed {
+            bleed_out,
+            revive_progress,
+        } = self
+        {
+            bleed_out.encode(out);
+            revive_progress.encode(out);
         }
     }
 
-    pub fn deserialize(packet: &[u8]) -> Result<Message, io::Error> {
-        if packet.is_empty() {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty packet"));
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        match u8::decode(reader)? {
+            LIFE_ALIVE => Ok(LifeState::Alive),
+            LIFE_DOWNED => Ok(LifeState::Downed {
+                bleed_out: f32::decode(reader)?,
+                revive_progress: f32::decode(reader)?,
+            }),
+            LIFE_DEAD => Ok(LifeState::Dead),
+            tag => Err(CodecError::UnknownTag {
+                what: "life state",
+                tag,
+            }),
         }
+    }
+}
 
-        println!("Deserializing packet: {:?}", packet);
-        match packet[0] {
-            ERROR if packet.len() >= 3 => {
-                let length = u16::from_le_bytes([packet[1], packet[2]]) as usize;
+impl Codec for ActorId {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (kind, id) = match self {
+            ActorId::Player(id) => (ACTOR_PLAYER, id),
+            ActorId::Enemy(id) => (ACTOR_ENEMY, id),
+        };
+        kind.encode(out);
+        id.encode(out);
+    }
 
-                if packet.len() < 3 + length {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Error message too short",
-                    ));
-                }
-                let error_msg = String::from_utf8(packet[3..3 + length].to_vec())
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        match u8::decode(reader)? {
+            ACTOR_PLAYER => Ok(ActorId::Player(PlayerID::decode(reader)?)),
+            ACTOR_ENEMY => Ok(ActorId::Enemy(EntityId::decode(reader)?)),
+            tag => Err(CodecError::UnknownTag {
+                what: "actor kind",
+                tag,
+            }),
+        }
+    }
+}
 
-                Ok(Message::Error(error_msg))
-            }
+impl Codec for RunOutcome {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            RunOutcome::Defeat => OUTCOME_DEFEAT.encode(out),
+            RunOutcome::Interrupted => OUTCOME_INTERRUPTED.encode(out),
+        }
+    }
 
-            PING => Ok(Message::Ping),
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        match u8::decode(reader)? {
+            OUTCOME_DEFEAT => Ok(RunOutcome::Defeat),
+            OUTCOME_INTERRUPTED => Ok(RunOutcome::Interrupted),
+            tag => Err(CodecError::UnknownTag {
+                what: "run outcome",
+                tag,
+            }),
+        }
+    }
+}
 
-            HANDSHAKE if packet.len() >= 3 => {
-                // Read the length of the username string
-                let length = u16::from_le_bytes([packet[1], packet[2]]) as usize;
+/// Always the last field of CREATE_ROOM, older clients leave it off and get the default
+impl Codec for GameMode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_u8().encode(out);
+    }
 
-                if packet.len() < 3 + length {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Packet too short! Missing name",
-                    ));
-                }
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        if reader.is_empty() {
+            return Ok(GameMode::default());
+        }
 
-                let name_bytes = &packet[3..3 + length];
-                let player_name = String::from_utf8(name_bytes.to_vec())
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tag = u8::decode(reader)?;
+        GameMode::from_u8(tag).ok_or(CodecError::UnknownTag {
+            what: "game mode",
+            tag,
+        })
+    }
+}
 
-                Ok(Message::Handshake(player_name))
-            }
+/// Always the last field of LEAVE, clients leave it off and get the default
+impl Codec for LeaveReason {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_u8().encode(out);
+    }
 
-            ACK => {
-                let player_id = u32::from_le_bytes([packet[1], packet[2], packet[3], packet[4]]);
-                Ok(Message::Ack(player_id))
-            }
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        if reader.is_empty() {
+            return Ok(LeaveReason::default());
+        }
 
-            LEAVE if packet.len() >= 5 => {
-                let player_id = u32::from_le_bytes([packet[1], packet[2], packet[3], packet[4]]);
+        let tag = u8::decode(reader)?;
+        LeaveReason::from_u8(tag).ok_or(CodecError::UnknownTag {
+            what: "leave reason",
+            tag,
+        })
+    }
+}
 
-                Ok(Message::Leave(player_id))
-            }
+/// Always the last field of HANDSHAKE, older clients leave it off and offer nothing
+impl Codec for CompressionOffer {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.algorithms.encode(out);
+        self.dictionary_id.encode(out);
+    }
 
-            CREATE_ROOM if packet.len() > 5 => {
-                let room_name_len = u16::from_le_bytes([packet[1], packet[2]]) as usize;
-                if packet.len() < 3 + room_name_len + 2 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Packet too short",
-                    ));
-                }
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        if reader.is_empty() {
+            return Ok(CompressionOffer::default());
+        }
 
-                let room_name = String::from_utf8(packet[3..3 + room_name_len].to_vec())
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(CompressionOffer {
+            algorithms: u8::decode(reader)?,
+            dictionary_id: u32::decode(reader)?,
+        })
+    }
+}
 
-                let pass_offset = 3 + room_name_len;
-                let pass_len =
-                    u16::from_le_bytes([packet[pass_offset], packet[pass_offset + 1]]) as usize;
-                let pass =
-                    String::from_utf8(packet[pass_offset + 2..pass_offset + pass_len + 2].to_vec())
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+/// Always the last field of ACK, older clients never see it
+impl Codec for Compression {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_u8().encode(out);
+    }
 
-                Ok(Message::CreateRoom(room_name, pass))
-            }
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        if reader.is_empty() {
+            return Ok(Compression::default());
+        }
 
-            JOIN_ROOM if packet.len() >= 7 => {
-                let room_id = u32::from_le_bytes(packet[1..5].try_into().map_err(|_| {
-                    io::Error::new(io::ErrorKind::InvalidData, "Packet must be 4 bytes")
-                })?);
-
-                let pass_len = u16::from_le_bytes([packet[5], packet[6]]) as usize;
-                if packet.len() < 7 + pass_len {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Packet too short for JOIN_ROOM",
-                    ));
-                }
+        let tag = u8::decode(reader)?;
+        Compression::from_u8(tag).ok_or(CodecError::UnknownTag {
+            what: "compression",
+            tag,
+        })
+    }
+}
 
-                let password = String::from_utf8(packet[7..7 + pass_len].to_vec())
-                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+/// Bit-packed, see `write_inputs`
+impl Codec for Vec<InputCommand> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut writer = BitWriter::default();
+        write_inputs(&mut writer, self);
+        out.extend_from_slice(&writer.finish());
+    }
 
-                Ok(Message::JoinRoom(room_id, password))
-            }
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let mut bits = BitReader::new(reader.rest());
+        let commands = read_inputs(&mut bits)?;
+        reader.take(bits.bytes_read())?;
+        Ok(commands)
+    }
+}
 
-            _ => Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!(
-                    "Unknow command {} or insufficient length {}",
-                    packet[0],
-                    packet.len()
-                ),
-            )),
+/// Bit-packed, see `write_snapshot`
+impl Codec for RoomSnapshot {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut writer = BitWriter::default();
+        write_snapshot(&mut writer, self);
+        out.extend_from_slice(&writer.finish());
+    }
+
+    fn decode(reader: &mut Reader) -> Result<Self, CodecError> {
+        let mut bits = BitReader::new(reader.rest());
+        let snapshot = read_snapshot(&mut bits)?;
+        reader.take(bits.bytes_read())?;
+        Ok(snapshot)
+    }
+}
+
+fn write_inputs(writer: &mut BitWriter, commands: &[InputCommand]) {
+    let count = commands.len().min(globals::MAX_INPUTS_PER_PACKET);
+    writer.write_bounded(count as u32, 0, globals::MAX_INPUTS_PER_PACKET as u32);
+
+    let mut previous: Option<InputSeq> = None;
+    for command in commands.iter().take(count) {
+        // Redundant inputs are nearly always consecutive, so that costs a single bit
+        match previous {
+            Some(previous) if command.seq == previous.wrapping_add(1) => writer.write_bool(true),
+            Some(_) => {
+                writer.write_bool(false);
+                writer.write_varint(command.seq);
+            }
+            None => writer.write_varint(command.seq),
+        }
+        previous = Some(command.seq);
+
+        match command.action {
+            InputAction::Move(x, y) => {
+                writer.write_bits(INPUT_MOVE as u32, 1);
+
+                let magnitude = (x * x + y * y).sqrt();
+                // Too small to survive quantization is the same as standing still
+                let moving = magnitude.is_finite() && MOVE_MAGNITUDE.snap(magnitude) > 0.0;
+                writer.write_bool(moving);
+                if moving {
+                    writer.write_angle(y.atan2(x), MOVE_ANGLE_BITS);
+                    writer.write_quantized(magnitude, MOVE_MAGNITUDE);
+                }
+            }
+            InputAction::Shoot(x, y) => {
+                writer.write_bits(INPUT_SHOOT as u32, 1);
+
+                // A shot with no direction hits nothing, it goes out as one
+                let aimed = x.is_finite() && y.is_finite() && (x != 0.0 || y != 0.0);
+                writer.write_bool(aimed);
+                if aimed {
+                    writer.write_angle(y.atan2(x), SHOOT_ANGLE_BITS);
+                }
+            }
         }
     }
 }
 
-////////////////////////////////////////////////
+fn read_inputs(reader: &mut BitReader) -> Result<Vec<InputCommand>, CodecError> {
+    let count = reader.read_bounded(0, globals::MAX_INPUTS_PER_PACKET as u32)? as usize;
+
+    let mut commands: Vec<InputCommand> = Vec::with_capacity(count);
+    for _ in 0..count {
+        let seq = match commands.last() {
+            Some(previous) if reader.read_bool()? => previous.seq.wrapping_add(1),
+            _ => reader.read_varint()?,
+        };
+
+        let action = if reader.read_bits(1)? == INPUT_MOVE as u32 {
+            if reader.read_bool()? {
+                let angle = reader.read_angle(MOVE_ANGLE_BITS)?;
+                let magnitude = reader.read_quantized(MOVE_MAGNITUDE)?;
+                InputAction::Move(angle.cos() * magnitude, angle.sin() * magnitude)
+            } else {
+                InputAction::Move(0.0, 0.0)
+            }
+        } else if reader.read_bool()? {
+            let angle = reader.read_angle(SHOOT_ANGLE_BITS)?;
+            InputAction::Shoot(angle.cos(), angle.sin())
+        } else {
+            InputAction::Shoot(0.0, 0.0)
+        };
+
+        commands.push(InputCommand { seq, action });
+    }
 
-static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+    Ok(commands)
+}
+
+fn write_position(writer: &mut BitWriter, position: Position, quantized: Quantized) {
+    writer.write_quantized(position.x, quantized);
+    writer.write_quantized(position.y, quantized);
+}
 
-pub fn set_trace(enabled: bool) {
-    TRACE_ENABLED.store(enabled, std::sync::atomic::Ordering::Relaxed);
+fn read_position(reader: &mut BitReader, quantized: Quantized) -> Result<Position, CodecError> {
+    Ok(Position::new(
+        reader.read_quantized(quantized)?,
+        reader.read_quantized(quantized)?,
+    ))
 }
 
-pub fn trace(s: String) {
-    if TRACE_ENABLED.load(std::sync::atomic::Ordering::Relaxed) {
-        println!("[TRACE] {s}");
+fn write_snapshot(writer: &mut BitWriter, snapshot: &RoomSnapshot) {
+    writer.write_varint(snapshot.tick);
+    writer.write_varint(snapshot.ack_input_seq);
+    write_position(writer, snapshot.ack_position, POSITION);
+
+    writer.write_varint(snapshot.players.len() as u32);
+    for player in snapshot.players.iter() {
+        writer.write_varint(player.id);
+        write_position(writer, player.position, POSITION);
+        write_position(writer, player.velocity, VELOCITY);
+        writer.write_signed_varint(player.health);
+        writer.write_varint(player.ping_ms as u32);
+        writer.write_bounded(player.life.as_u8() as u32, 0, LIFE_DEAD as u32);
+
+        // Downed players also carry their timers
+        if let LifeState::Downed {
+            bleed_out,
+            revive_progress,
+        } = player.life
+        {
+            writer.write_quantized(bleed_out, DOWNED_TIMER);
+            writer.write_quantized(revive_progress, DOWNED_TIMER);
+        }
     }
+
+    writer.write_varint(snapshot.enemies.len() as u32);
+    for enemy in snapshot.enemies.iter() {
+        writer.write_varint(enemy.id);
+        write_position(writer, enemy.position, POSITION);
+        writer.write_signed_varint(enemy.health);
+    }
+}
+
+fn read_snapshot(reader: &mut BitReader) -> Result<RoomSnapshot, CodecError> {
+    let tick = reader.read_varint()?;
+    let ack_input_seq = reader.read_varint()?;
+    let ack_position = read_position(reader, POSITION)?;
+
+    // Counts come off the wire, so never trust them for preallocation
+    let player_count = reader.read_varint()?;
+    let mut players = Vec::new();
+    for _ in 0..player_count {
+        let id = reader.read_varint()?;
+        let position = read_position(reader, POSITION)?;
+        let velocity = read_position(reader, VELOCITY)?;
+        let health = reader.read_signed_varint()?;
+        let ping_ms = reader.read_varint()?.min(u16::MAX as u32) as u16;
+
+        let life = match reader.read_bounded(0, LIFE_DEAD as u32)? as u8 {
+            LIFE_ALIVE => LifeState::Alive,
+            LIFE_DOWNED => LifeState::Downed {
+                bleed_out: reader.read_quantized(DOWNED_TIMER)?,
+                revive_progress: reader.read_quantized(DOWNED_TIMER)?,
+            },
+            _ => LifeState::Dead,
+        };
+
+        players.push(PlayerSnapshot {
+            id,
+            position,
+            velocity,
+            health,
+            ping_ms,
+            life,
+        });
+    }
+
+    let enemy_count = reader.read_varint()?;
+    let mut enemies = Vec::new();
+    for _ in 0..enemy_count {
+        enemies.push(EnemySnapshot {
+            id: reader.read_varint()?,
+            position: read_position(reader, POSITION)?,
+            health: reader.read_signed_varint()?,
+        });
+    }
+
+    Ok(RoomSnapshot {
+        tick,
+        ack_input_seq,
+        ack_position,
+        players,
+        enemies,
+    })
 }
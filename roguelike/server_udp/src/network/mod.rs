@@ -1 +1,9 @@
+pub mod bandwidth;
+pub mod batch;
+pub mod bits;
+pub mod codec;
+pub mod compress;
+pub mod fragment;
 pub mod message;
+pub mod rtt;
+pub mod socket;
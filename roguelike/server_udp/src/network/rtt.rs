@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+/// Smoothed round trip time and jitter, same weights as TCP (RFC 6298)
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RttEstimator {
+    smoothed: Option<Duration>,
+    jitter: Duration,
+    samples: u32,
+}
+
+impl RttEstimator {
+    pub fn add_sample(&mut self, sample: Duration) {
+        self.samples += 1;
+
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(sample);
+                self.jitter = sample / 2;
+            }
+            Some(smoothed) => {
+                let deviation = smoothed.abs_diff(sample);
+                self.jitter = (self.jitter * 3 + deviation) / 4;
+                self.smoothed = Some((smoothed * 7 + sample) / 8);
+            }
+        }
+    }
+
+    /// Zero until the first pong comes back
+    pub fn smoothed(&self) -> Duration {
+        self.smoothed.unwrap_or_default()
+    }
+
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+}
+
+/// Milliseconds and ticks since the server started. Clients estimate server time as
+/// `time_ms + rtt / 2` from a PONG. Rooms number their snapshots with the same ticks.
+#[derive(Debug)]
+pub struct ServerClock {
+    started_at: Instant,
+    tick_rate_hz: u64,
+}
+
+impl ServerClock {
+    pub fn new(tick_rate_hz: u64) -> Self {
+        ServerClock {
+            started_at: Instant::now(),
+            tick_rate_hz,
+        }
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+
+    pub fn tick(&self) -> u32 {
+        (self.started_at.elapsed().as_nanos() / self.tick_interval().as_nanos()) as u32
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.tick_rate_hz as u32
+    }
+
+    /// Halfway through the next tick, so a timer started there with `tick_interval`
+    /// sees every tick once even with some scheduling jitter
+    pub fn next_mid_tick(&self) -> Instant {
+        let interval = self.tick_interval();
+        self.started_at + interval * (self.tick() + 1) + interval / 2
+    }
+}
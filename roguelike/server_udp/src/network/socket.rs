@@ -0,0 +1,361 @@
+use std::{
+    io,
+    net::{SocketAddr, SocketAddrV6},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+
+#[cfg(all(target_os = "linux", feature = "mmsg"))]
+use std::os::fd::AsRawFd;
+#[cfg(all(target_os = "linux", feature = "mmsg"))]
+use tokio::io::Interest;
+
+#[cfg(all(target_os = "linux", feature = "mmsg"))]
+use crate::config::globals;
+
+/// Whether `RecvBatch::recv` and `send_many` use one syscall for many datagrams
+pub const BATCHED: bool = cfg!(all(target_os = "linux", feature = "mmsg"));
+
+/// Open `count` sockets on the same address. More than one shares the port through
+/// SO_REUSEPORT and the kernel keeps each client on one of them. An IPv6 socket that is
+/// not `only_v6` also takes IPv4 clients, as IPv4-mapped addresses.
+pub fn bind(addr: SocketAddr, count: usize, only_v6: bool) -> io::Result<Vec<UdpSocket>> {
+    (0..count.max(1))
+        .map(|_| {
+            let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(only_v6)?;
+            }
+            if count > 1 {
+                set_reuse_port(&socket)?;
+            }
+            socket.set_nonblocking(true)?;
+            socket.bind(&addr.into())?;
+
+            UdpSocket::from_std(socket.into())
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_socket: &Socket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not available on this platform",
+    ))
+}
+
+/// The one form an address is known by. A dual-stack socket reports IPv4 clients as
+/// IPv4-mapped IPv6 addresses, which would otherwise make them a different client.
+pub fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// An IPv6 socket can only send to IPv4 clients through their mapped address
+fn routable(addr: SocketAddr, ipv6_socket: bool) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) if ipv6_socket => {
+            SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0).into()
+        }
+        _ => addr,
+    }
+}
+
+fn is_ipv6(socket: &UdpSocket) -> bool {
+    socket.local_addr().is_ok_and(|addr| addr.is_ipv6())
+}
+
+/// `send_to` that also reaches IPv4 clients from a dual-stack socket
+pub async fn send_to(socket: &UdpSocket, packet: &[u8], addr: SocketAddr) -> io::Result<usize> {
+    socket
+        .send_to(packet, routable(addr, is_ipv6(socket)))
+        .await
+}
+
+/// Buffers for reading several datagrams at once
+pub struct RecvBatch {
+    buffers: Vec<Vec<u8>>,
+    /// Buffer index, length and sender of each datagram read
+    received: Vec<(usize, usize, SocketAddr)>,
+}
+
+impl RecvBatch {
+    /// Room for `count` datagrams of up to `size` bytes each
+    pub fn new(count: usize, size: usize) -> Self {
+        RecvBatch {
+            buffers: vec![vec![0; size]; count.max(1)],
+            received: Vec::with_capacity(count),
+        }
+    }
+
+    /// Wait for at least one datagram and read whatever else is already waiting, up to
+    /// the batch size
+    pub async fn recv(&mut self, socket: &UdpSocket) -> io::Result<()> {
+        #[cfg(all(target_os = "linux", feature = "mmsg"))]
+        {
+            let fd = socket.as_raw_fd();
+            loop {
+                socket.readable().await?;
+                match socket.try_io(Interest::READABLE, || {
+                    mmsg::recvmmsg(fd, &mut self.buffers, &mut self.received)
+                }) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return result,
+                }
+            }
+        }
+
+        #[cfg(not(all(target_os = "linux", feature = "mmsg")))]
+        self.recv_each(socket).await
+    }
+
+    /// The portable path: one recv_from per datagram
+    pub async fn recv_each(&mut self, socket: &UdpSocket) -> io::Result<()> {
+        self.received.clear();
+        let (len, addr) = socket.recv_from(&mut self.buffers[0]).await?;
+        self.received.push((0, len, addr));
+
+        // An error here belongs to the next call, the datagrams already read are still good
+        for (index, buffer) in self.buffers.iter_mut().enumerate().skip(1) {
+            match socket.try_recv_from(buffer) {
+                Ok((len, addr)) => self.received.push((index, len, addr)),
+                Err(_) => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Datagrams read by the last call, in the order they arrived
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], SocketAddr)> {
+        self.received
+            .iter()
+            .map(|(index, len, addr)| (&self.buffers[*index][..*len], *addr))
+    }
+}
+
+/// Send every packet to its address, as many per syscall as the platform allows. Returns
+/// the packets that could not be sent.
+pub async fn send_many(
+    socket: &UdpSocket,
+    packets: &[(Vec<u8>, SocketAddr)],
+) -> Vec<(SocketAddr, io::Error)> {
+    #[cfg(all(target_os = "linux", feature = "mmsg"))]
+    {
+        let fd = socket.as_raw_fd();
+        let ipv6 = is_ipv6(socket);
+        let mut failed = Vec::new();
+        let mut rest = packets;
+
+        while !rest.is_empty() {
+            let batch = &rest[..rest.len().min(globals::SEND_BATCH_SIZE)];
+
+            let sent = match socket.writable().await {
+                Ok(()) => socket.try_io(Interest::WRITABLE, || mmsg::sendmmsg(fd, batch, ipv6)),
+                Err(e) => Err(e),
+            };
+
+            match sent {
+                Ok(sent) => rest = &rest[sent..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                // Only the first packet of the batch failed, carry on with the others
+                Err(e) => {
+                    failed.push((rest[0].1, e));
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        failed
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "mmsg")))]
+    send_each(socket, packets).await
+}
+
+/// The portable path: one send_to per packet
+pub async fn send_each(
+    socket: &UdpSocket,
+    packets: &[(Vec<u8>, SocketAddr)],
+) -> Vec<(SocketAddr, io::Error)> {
+    let mut failed = Vec::new();
+
+    for (packet, addr) in packets {
+        if let Err(e) = send_to(socket, packet, *addr).await {
+            failed.push((*addr, e));
+        }
+    }
+
+    failed
+}
+
+#[cfg(all(target_os = "linux", feature = "mmsg"))]
+mod mmsg {
+    use std::{
+        io, mem,
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+        os::fd::RawFd,
+        ptr,
+    };
+
+    fn to_raw(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // SAFETY: all zeroes is a valid sockaddr_storage
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: sockaddr_storage is big enough and aligned for any address family
+                let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                raw.sin_family = libc::AF_INET as libc::sa_family_t;
+                raw.sin_port = addr.port().to_be();
+                raw.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: as above
+                let raw = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                raw.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                raw.sin6_port = addr.port().to_be();
+                raw.sin6_flowinfo = addr.flowinfo();
+                raw.sin6_addr.s6_addr = addr.ip().octets();
+                raw.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        (storage, len as libc::socklen_t)
+    }
+
+    fn from_raw(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                // SAFETY: the family says this is a sockaddr_in
+                let raw = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(raw.sin_addr.s_addr.to_ne_bytes()),
+                    u16::from_be(raw.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                // SAFETY: the family says this is a sockaddr_in6
+                let raw = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(raw.sin6_addr.s6_addr),
+                    u16::from_be(raw.sin6_port),
+                    raw.sin6_flowinfo,
+                    raw.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    /// How many of `packets` went out, counting from the first
+    pub fn sendmmsg(fd: RawFd, packets: &[(Vec<u8>, SocketAddr)], ipv6: bool) -> io::Result<usize> {
+        let mut addrs: Vec<_> = packets
+            .iter()
+            .map(|(_, addr)| to_raw(&super::routable(*addr, ipv6)))
+            .collect();
+        let mut iovecs: Vec<libc::iovec> = packets
+            .iter()
+            .map(|(packet, _)| libc::iovec {
+                iov_base: packet.as_ptr() as *mut libc::c_void,
+                iov_len: packet.len(),
+            })
+            .collect();
+
+        let mut headers: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(iovecs.iter_mut())
+            .map(|((addr, len), iovec)| {
+                // SAFETY: all zeroes is a valid mmsghdr
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+                header.msg_hdr.msg_namelen = *len;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        // SAFETY: the headers point into `addrs`, `iovecs` and `packets`, which all outlive
+        // the call, and the kernel only reads the packets
+        let sent =
+            unsafe { libc::sendmmsg(fd, headers.as_mut_ptr(), headers.len() as libc::c_uint, 0) };
+
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+
+    /// Read into `buffers`, filling `received` with the length and sender of each datagram
+    pub fn recvmmsg(
+        fd: RawFd,
+        buffers: &mut [Vec<u8>],
+        received: &mut Vec<(usize, usize, SocketAddr)>,
+    ) -> io::Result<()> {
+        // SAFETY: all zeroes is a valid sockaddr_storage
+        let mut addrs: Vec<libc::sockaddr_storage> = vec![unsafe { mem::zeroed() }; buffers.len()];
+        let mut iovecs: Vec<libc::iovec> = buffers
+            .iter_mut()
+            .map(|buffer| libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            })
+            .collect();
+
+        let mut headers: Vec<libc::mmsghdr> = addrs
+            .iter_mut()
+            .zip(iovecs.iter_mut())
+            .map(|(addr, iovec)| {
+                // SAFETY: all zeroes is a valid mmsghdr
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = addr as *mut _ as *mut libc::c_void;
+                header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as u32;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+
+        // SAFETY: the headers point into `addrs`, `iovecs` and `buffers`, which all outlive
+        // the call, and each iovec covers exactly its buffer
+        let count = unsafe {
+            libc::recvmmsg(
+                fd,
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                0,
+                ptr::null_mut(),
+            )
+        };
+
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        received.clear();
+        for (index, (header, addr)) in headers.iter().zip(addrs.iter()).enumerate() {
+            if index == count as usize {
+                break;
+            }
+            if let Some(addr) = from_raw(addr) {
+                received.push((index, header.msg_len as usize, addr));
+            }
+        }
+        Ok(())
+    }
+}
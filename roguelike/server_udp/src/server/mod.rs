@@ -0,0 +1,1093 @@
+use crate::{
+    config::{
+        globals::{
+            self,
+            commands::{COMPRESSED, FRAGMENT, PING},
+        },
+        settings::ServerSettings,
+    },
+    game::{
+        input::{InputAction, InputCommand},
+        mode::GameMode,
+        player::{LeaveReason, Player, PlayerID},
+        room::{Room, RoomId, RoomName, RoomPass},
+    },
+    logging,
+    network::{
+        batch,
+        compress::{CompressionOffer, Compressor},
+        fragment::{self, FragmentError, Reassembler},
+        message::Message,
+        rtt::ServerClock,
+        socket::{self, RecvBatch},
+    },
+};
+use pipeline::{Datagram, ReceivePipeline};
+use room_actor::RoomCommand;
+use router::{Router, Session};
+use shutdown::Shutdown;
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    hash::{BuildHasher, RandomState},
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, MutexGuard, RwLock,
+        atomic::{AtomicU16, AtomicU32},
+    },
+    time::Instant,
+};
+use tokio::{
+    self,
+    net::UdpSocket,
+    sync::{Mutex, Notify, mpsc},
+};
+use tracing::{Instrument, Span, debug, field, info, info_span, trace, warn};
+
+mod pipeline;
+mod reload;
+mod room_actor;
+mod router;
+mod shutdown;
+
+//////////////////////////////////////////////////////////////////
+
+type ServerSessionResult<T = ()> = Result<T, Box<dyn Error + Send + Sync>>;
+
+type ChannelSender = mpsc::UnboundedSender<BroadcastMessage>;
+type ChannelReceiver = mpsc::UnboundedReceiver<BroadcastMessage>;
+
+struct BroadcastMessage {
+    msg: Vec<u8>,
+    excluded_client: Option<SocketAddr>,
+    // None sends to every connected player
+    recipients: Option<Vec<SocketAddr>>,
+}
+
+struct ServerContext {
+    // Swapped as a whole when the config is reloaded
+    settings: RwLock<Arc<ServerSettings>>,
+    // One or more per bind address, each read by its own listen_handler
+    server_sockets: Vec<UdpSocket>,
+    // Picks the socket for clients without a session
+    shard_hasher: RandomState,
+    broadcast_tx: ChannelSender,
+    // Sessions by address and the room actors they are routed to
+    router: Router,
+    clock: ServerClock,
+    next_ping_seq: AtomicU32,
+    // Recent pings by sequence id, so a pong tells us the round trip
+    pending_pings: Mutex<VecDeque<(u32, Instant)>>,
+    next_fragmented_id: AtomicU16,
+    // One per receive worker, so workers never wait on each other to reassemble
+    reassemblers: Vec<std::sync::Mutex<Reassembler>>,
+    compressor: Compressor,
+    receive: ReceivePipeline,
+    // Signalled by each room once its tick's messages are queued
+    flush_queues: Notify,
+    shutdown: Shutdown,
+}
+
+impl ServerContext {
+    fn settings(&self) -> Arc<ServerSettings> {
+        self.settings
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    fn new(
+        settings: ServerSettings,
+        server_sockets: Vec<UdpSocket>,
+        broadcast_tx: ChannelSender,
+        compressor: Compressor,
+        receive: ReceivePipeline,
+    ) -> ServerContext {
+        Self {
+            reassemblers: (0..receive.workers())
+                .map(|_| std::sync::Mutex::new(Reassembler::new(settings.fragment)))
+                .collect(),
+            compressor,
+            receive,
+            settings: RwLock::new(Arc::new(settings)),
+            router: Router::new(),
+            clock: ServerClock::new(globals::TICK_RATE_HZ),
+            next_ping_seq: AtomicU32::new(1),
+            pending_pings: Mutex::new(VecDeque::new()),
+            flush_queues: Notify::new(),
+            shutdown: Shutdown::new(),
+            next_fragmented_id: AtomicU16::new(0),
+            server_sockets,
+            shard_hasher: RandomState::new(),
+            broadcast_tx,
+        }
+    }
+
+    /// The socket a client last reached us on, so replies come from the address it sent
+    /// to. Clients without a session get one of the same family when there is one.
+    fn shard(&self, client: &SocketAddr) -> usize {
+        if let Some(socket) = self.router.with_session(client, |session| session.socket) {
+            return socket;
+        }
+
+        let same_family: Vec<usize> = (0..self.server_sockets.len())
+            .filter(|&i| {
+                self.server_sockets[i]
+                    .local_addr()
+                    .is_ok_and(|addr| addr.is_ipv4() == client.is_ipv4())
+            })
+            .collect();
+        let hash = self.shard_hasher.hash_one(client) as usize;
+        if same_family.is_empty() {
+            hash % self.server_sockets.len()
+        } else {
+            same_family[hash % same_family.len()]
+        }
+    }
+
+    /// Fragments from a client are only ever reassembled by its own worker
+    fn reassembler(&self, client: &SocketAddr) -> MutexGuard<'_, Reassembler> {
+        self.reassemblers[self.receive.worker(client)]
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn socket_for(&self, client: &SocketAddr) -> &UdpSocket {
+        &self.server_sockets[self.shard(client)]
+    }
+}
+
+/// A running server, kept around to shut it down
+pub struct ServerHandle {
+    context: Arc<ServerContext>,
+}
+
+impl ServerHandle {
+    /// Apply settings that are safe to change while running and log what changed.
+    /// Nothing is applied when any of them needs a restart.
+    pub async fn reload(&self, settings: ServerSettings) -> Result<(), String> {
+        let changes = reload::apply(&self.context, settings).await?;
+        if changes.is_empty() {
+            info!("Settings reloaded, nothing changed");
+        }
+        for change in changes {
+            info!(
+                key = change.key,
+                old = %change.old,
+                new = %change.new,
+                "Settings reloaded"
+            );
+        }
+        Ok(())
+    }
+
+    /// Tell clients why and when, let rooms finish within the grace period, then stop
+    /// every task
+    pub async fn shutdown(self, reason: &str) {
+        shutdown::coordinate(&self.context, reason).await;
+    }
+}
+
+//-------------------------------------
+
+// Function to create new server
+pub async fn start_server(settings: ServerSettings) -> ServerSessionResult<ServerHandle> {
+    settings
+        .validate()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let compressor = Compressor::new(settings.compression.clone())?;
+
+    match tokio::time::timeout(globals::CONNECTION_TIMEOUT_SEC, async {
+        // An IPv6 wildcard next to an IPv4 address would also claim its port for IPv4
+        let only_v6 = settings.bind.iter().any(|ip| ip.is_ipv4());
+        let mut server_sockets = Vec::new();
+        for ip in settings.bind.iter() {
+            let address = SocketAddr::new(*ip, settings.port);
+            server_sockets.extend(socket::bind(address, settings.sockets, only_v6)?);
+            info!(%address, "Listening");
+        }
+        let (broadcast_tx, broadcast_rx) = mpsc::unbounded_channel::<BroadcastMessage>();
+        let (receive, receive_queues) = ReceivePipeline::new(
+            settings.receive_workers,
+            settings.receive_queue_depth,
+            globals::RECEIVE_BUFFER_POOL,
+        );
+
+        let context = Arc::new(ServerContext::new(
+            settings,
+            server_sockets,
+            broadcast_tx,
+            compressor,
+            receive,
+        ));
+
+        let shutdown = &context.shutdown;
+        for (worker, queue) in receive_queues.into_iter().enumerate() {
+            shutdown.spawn(receive_worker(context.clone(), worker, queue));
+        }
+        shutdown.spawn(report_receive_stats(context.clone()));
+        // Every socket feeds the same workers, so any client can reach any room
+        for shard in 0..context.server_sockets.len() {
+            shutdown.spawn(listen_handler(context.clone(), shard));
+        }
+        shutdown.spawn_graceful(broadcast_handler(context.clone(), broadcast_rx));
+
+        // Healthcheck server
+        shutdown.spawn(ping_sender(context.clone()));
+
+        // Cleanup inactive player
+        shutdown.spawn(cleanup_inactive(context.clone()));
+
+        Ok(ServerHandle {
+            context: context.clone(),
+        })
+    })
+    .await
+    {
+        Ok(result) => result,
+        Err(e) => Err(format!(
+            "Server took too long to start - timeout after {} seconds: {e}",
+            globals::CONNECTION_TIMEOUT_SEC.as_secs()
+        )
+        .into()),
+    }
+}
+
+/// Queue outgoing messages per client and flush them at the end of every tick, packed
+/// into as few datagrams as the MTU allows
+async fn broadcast_handler(context: Arc<ServerContext>, mut broadcast_rx: ChannelReceiver) {
+    let mut queues: HashMap<SocketAddr, Vec<Vec<u8>>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            message = broadcast_rx.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+                queue_message(&context, &mut queues, message);
+            }
+
+            _ = context.flush_queues.notified() => {
+                flush_queues(&context, &mut queues, &mut broadcast_rx).await;
+            }
+
+            // Whatever the rooms queued on their way out still goes
+            _ = context.shutdown.stopping().cancelled() => {
+                flush_queues(&context, &mut queues, &mut broadcast_rx).await;
+                break;
+            }
+        }
+    }
+}
+
+async fn flush_queues(
+    context: &ServerContext,
+    queues: &mut HashMap<SocketAddr, Vec<Vec<u8>>>,
+    broadcast_rx: &mut ChannelReceiver,
+) {
+    // Pick up whatever the tick queued before the flush was signalled
+    while let Ok(message) = broadcast_rx.try_recv() {
+        queue_message(context, queues, message);
+    }
+
+    // Taking the whole map also forgets clients that have left
+    let mut outgoing = Vec::new();
+    for (addr, messages) in std::mem::take(queues) {
+        flush_queue(context, addr, messages, &mut outgoing);
+    }
+
+    // Every client's datagrams for the tick go out together
+    for (addr, e) in send_all(context, outgoing).await {
+        warn!(%addr, "Error sending queued messages: {}", e);
+    }
+}
+
+fn queue_message(
+    context: &ServerContext,
+    queues: &mut HashMap<SocketAddr, Vec<Vec<u8>>>,
+    message: BroadcastMessage,
+) {
+    let recipients: Vec<SocketAddr> = match message.recipients {
+        Some(recipients) => recipients,
+        None => context.router.addresses(),
+    };
+
+    for addr in recipients {
+        if message.excluded_client == Some(addr) {
+            continue;
+        }
+
+        let queue = queues.entry(addr).or_default();
+        if queue.len() >= globals::MAX_QUEUED_MESSAGES {
+            warn!(%addr, "Send queue is full, dropping message");
+            continue;
+        }
+        queue.push(message.msg.clone());
+    }
+}
+
+// Turn a client's queue into the datagrams that carry it
+fn flush_queue(
+    context: &ServerContext,
+    addr: SocketAddr,
+    messages: Vec<Vec<u8>>,
+    outgoing: &mut Vec<(Vec<u8>, SocketAddr)>,
+) {
+    let messages = compress_queue(context, addr, messages);
+
+    for packet in batch::pack(&messages, context.settings().fragment.mtu) {
+        match split_packet(context, &packet) {
+            Ok(fragments) => {
+                outgoing.extend(fragments.into_iter().map(|fragment| (fragment, addr)))
+            }
+            Err(e) => {
+                warn!(%addr, "Error sending queued messages: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Compress each queued message on its own with whatever the client negotiated, so the
+/// batch still splits into messages the client can decode one by one. What goes out is
+/// charged to the client's bandwidth budget.
+fn compress_queue(
+    context: &ServerContext,
+    addr: SocketAddr,
+    messages: Vec<Vec<u8>>,
+) -> Vec<Vec<u8>> {
+    let Some(compression) = context
+        .router
+        .with_session(&addr, |session| session.compression)
+    else {
+        return messages;
+    };
+
+    let mut sizes = Vec::with_capacity(messages.len());
+    let messages: Vec<Vec<u8>> = messages
+        .into_iter()
+        .map(|message| {
+            let bytes_in = message.len();
+            let packet = context
+                .compressor
+                .compress(&message, compression)
+                .unwrap_or(message);
+            sizes.push((bytes_in, packet.len()));
+            packet
+        })
+        .collect();
+
+    context.router.with_session(&addr, |session| {
+        for (bytes_in, bytes_out) in sizes {
+            session.compression_stats.record(bytes_in, bytes_out);
+            session.bandwidth.on_sent(bytes_out);
+        }
+    });
+
+    messages
+}
+
+/// Split a message into fragments when it does not fit the configured MTU
+fn split_packet(context: &ServerContext, msg: &[u8]) -> Result<Vec<Vec<u8>>, FragmentError> {
+    let message_id = context
+        .next_fragmented_id
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+    fragment::split(msg, context.settings().fragment.mtu, message_id)
+}
+
+async fn send_packet(context: &ServerContext, msg: &[u8], client: SocketAddr) -> io::Result<()> {
+    let packets =
+        split_packet(context, msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    for packet in packets.iter() {
+        trace!(target: logging::PACKETS, %client, bytes = packet.len(), "Datagram sent");
+        socket::send_to(context.socket_for(&client), packet, client).await?;
+    }
+
+    Ok(())
+}
+
+/// Send on each client's own socket, many datagrams per call
+async fn send_all(
+    context: &ServerContext,
+    packets: Vec<(Vec<u8>, SocketAddr)>,
+) -> Vec<(SocketAddr, io::Error)> {
+    let mut shards = vec![Vec::new(); context.server_sockets.len()];
+    for (packet, client) in packets {
+        trace!(target: logging::PACKETS, %client, bytes = packet.len(), "Datagram sent");
+        shards[context.shard(&client)].push((packet, client));
+    }
+
+    let mut failed = Vec::new();
+    for (socket, packets) in context.server_sockets.iter().zip(shards) {
+        failed.extend(socket::send_many(socket, &packets).await);
+    }
+    failed
+}
+
+// Handle request come in
+async fn listen_handler(context: Arc<ServerContext>, shard: usize) {
+    // Big enough for any UDP datagram, so nothing is silently truncated
+    let mut batch = RecvBatch::new(globals::RECV_BATCH_SIZE, globals::MAX_DATAGRAM_SIZE);
+
+    loop {
+        match batch.recv(&context.server_sockets[shard]).await {
+            Ok(()) => {
+                for (buf, client) in batch.iter().filter(|(buf, _)| !buf.is_empty()) {
+                    let client = socket::canonical(client);
+                    trace!(target: logging::PACKETS, %client, bytes = buf.len(), "Datagram received");
+
+                    // Copied into a recycled buffer so the socket can read the next batch
+                    let mut payload = context.receive.buffer();
+                    payload.extend_from_slice(buf);
+                    context.receive.dispatch(Datagram {
+                        client,
+                        socket: shard,
+                        payload,
+                    });
+                }
+            }
+
+            // This error happend when client close connection but server keep sending
+            // ping to that client and client machine send back the error
+            // To fix this, the server will have a cleanup method to check inactive
+            // user then remove them so the error will no longer happend
+            Err(e) if e.raw_os_error() == Some(10054) => {
+                debug!("client disconnected (os error 10054), continue...")
+            }
+            Err(e) => {
+                warn!("Error receiving UDP packet: {e}");
+            }
+        }
+    }
+}
+
+/// Handle one worker's share of clients, one datagram at a time
+async fn receive_worker(
+    context: Arc<ServerContext>,
+    worker: usize,
+    mut queue: mpsc::Receiver<Datagram>,
+) {
+    let mut expiry = tokio::time::interval(globals::FRAGMENT_EXPIRY_INTERVAL);
+
+    loop {
+        tokio::select! {
+            datagram = queue.recv() => {
+                let Some(datagram) = datagram else {
+                    break;
+                };
+                receive(&context, datagram).await;
+            }
+
+            _ = expiry.tick() => {
+                context.reassemblers[worker]
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .expire(Instant::now());
+            }
+        }
+    }
+}
+
+async fn receive(context: &Arc<ServerContext>, datagram: Datagram) {
+    let Datagram {
+        client,
+        socket,
+        payload,
+    } = datagram;
+
+    // Nothing from a banned address gets looked at
+    if !context.settings().banned.contains(&client.ip()) {
+        let player = context.router.with_session(&client, |session| session.id);
+        receive_datagram(context.clone(), client, socket, &payload)
+            .instrument(client_span(client, player))
+            .await;
+    }
+
+    context.receive.recycle(payload);
+}
+
+/// Everything logged while handling a client carries its address, and its player id
+/// once it has one
+fn client_span(client: SocketAddr, player: Option<PlayerID>) -> Span {
+    let span = info_span!("client", addr = %client, player = field::Empty);
+    if let Some(player) = player {
+        span.record("player", player);
+    }
+    span
+}
+
+async fn receive_datagram(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    socket: usize,
+    payload: &[u8],
+) {
+    if payload[0] == FRAGMENT {
+        let reassembled = context
+            .reassembler(&client)
+            .receive(client, payload, Instant::now());
+
+        match reassembled {
+            Ok(Some(packet)) => process_datagram(context, client, socket, &packet).await,
+            Ok(None) => {}
+            Err(e) => warn!("Rejected fragment: {}", e),
+        }
+    } else {
+        process_datagram(context, client, socket, payload).await;
+    }
+}
+
+/// Log how far behind the receive workers are, while there is traffic
+async fn report_receive_stats(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(globals::RECEIVE_STATS_INTERVAL);
+    let mut last_received = 0;
+
+    loop {
+        interval.tick().await;
+
+        let stats = context.receive.stats();
+        if stats.received == last_received {
+            continue;
+        }
+        last_received = stats.received;
+
+        let reassembling: usize = context
+            .reassemblers
+            .iter()
+            .map(|reassembler| {
+                reassembler
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .pending_bytes()
+            })
+            .sum();
+        debug!(
+            depths = ?stats.depths,
+            peak = stats.peak_depth,
+            received = stats.received,
+            dropped = stats.dropped,
+            reassembling,
+            "Receive queues"
+        );
+    }
+}
+
+// Messages batched into one datagram are handled in the order they were packed
+async fn process_datagram(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    socket: usize,
+    packet: &[u8],
+) {
+    match batch::unpack(packet) {
+        Ok(messages) => {
+            for msg in messages {
+                let msg = if msg[0] == COMPRESSED {
+                    match context.compressor.decompress(msg) {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            warn!("Rejected compressed message: {}", e);
+                            continue;
+                        }
+                    }
+                } else {
+                    msg.to_vec()
+                };
+
+                process_client_message(context.clone(), client, socket, msg).await;
+            }
+        }
+        Err(e) => warn!("Rejected batch: {}", e),
+    }
+}
+
+async fn process_client_message(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    socket: usize,
+    packet: Vec<u8>,
+) {
+    if packet.is_empty() {
+        return;
+    }
+    let command = packet[0];
+    trace!(target: logging::PACKETS, "Command received: {} (0x{:02x})", command, command);
+
+    // Older clients echo a bare PING byte, which decodes like a fresh Ping(0, 0)
+    let legacy_pong = packet == [PING];
+    let message = Message::deserialize(&packet);
+
+    // Any packet that parses proves the client is still there
+    if message.is_ok() {
+        context.router.with_session(&client, |session| {
+            session.last_active = Instant::now();
+            session.socket = socket;
+        });
+    }
+
+    match message {
+        Ok(Message::Error(msg)) => {
+            warn!("Received unexpected error message: {}", msg);
+        }
+
+        // An echo from an older client, match it against the newest ping
+        Ok(Message::Ping(..)) if legacy_pong => {
+            if !context.router.contains(&client) {
+                return;
+            }
+
+            let sent_at = context.pending_pings.lock().await.back().map(|(_, at)| *at);
+            record_pong(&context, client, sent_at);
+        }
+
+        // Clock sync request, answer with our time so the client can estimate server time
+        Ok(Message::Ping(seq, client_time_ms)) => {
+            if !context.router.contains(&client) {
+                return;
+            }
+
+            let pong = Message::Pong(
+                seq,
+                client_time_ms,
+                context.clock.now_ms(),
+                context.clock.tick(),
+            );
+            if let Err(e) = send_packet(&context, &pong.serialize(), client).await {
+                warn!("Failed to send PONG: {}", e);
+            }
+        }
+
+        Ok(Message::Pong(seq, _, _, _)) => {
+            if !context.router.contains(&client) {
+                return;
+            }
+
+            let sent_at = context
+                .pending_pings
+                .lock()
+                .await
+                .iter()
+                .find(|(pending, _)| *pending == seq)
+                .map(|(_, at)| *at);
+            record_pong(&context, client, sent_at);
+        }
+
+        Ok(Message::SnapshotAck(tick)) => {
+            context.router.with_session(&client, |session| {
+                session.bandwidth.on_ack(tick, Instant::now())
+            });
+        }
+
+        Ok(Message::Handshake(player_name, offer)) => {
+            if let Err(e) =
+                accept_client(context.clone(), client, socket, &player_name, offer).await
+            {
+                warn!(name = %player_name, "Failed to accept client: {}", e);
+
+                send_error_msg("Handshake message failed ", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::Leave(player_id, _)) => {
+            debug!("Drop player {}", player_id);
+            if let Err(e) = drop_player(context.clone(), client, LeaveReason::Quit).await {
+                warn!("Failed to drop player {}: {}", player_id, e);
+
+                send_error_msg("LEAVE message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::CreateRoom(room_name, password, mode)) => {
+            if let Err(e) = create_room(context.clone(), client, room_name, password, mode) {
+                send_error_msg("Create room failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::JoinRoom(room_id, password)) => {
+            if let Err(e) = join_room(&context, client, room_id, password) {
+                send_error_msg("Join room failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::PlayerInput(player_id, commands)) => {
+            if let Err(e) = buffer_inputs(&context, client, player_id, commands) {
+                send_error_msg("PLAYER_INPUT message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::VoteDescend) => {
+            let command = RoomCommand::VoteDescend { client };
+            if let Err(e) = context.router.route(&client, command) {
+                send_error_msg("VOTE_DESCEND message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::UseItem(slot)) => {
+            let command = RoomCommand::UseItem { client, slot };
+            if let Err(e) = context.router.route(&client, command) {
+                send_error_msg("USE_ITEM message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::DropItem(slot, count)) => {
+            let command = RoomCommand::DropItem {
+                client,
+                slot,
+                count,
+            };
+            if let Err(e) = context.router.route(&client, command) {
+                send_error_msg("DROP_ITEM message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::Equip(slot)) => {
+            let command = RoomCommand::Equip { client, slot };
+            if let Err(e) = context.router.route(&client, command) {
+                send_error_msg("EQUIP message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Ok(Message::Interact(entity_id)) => {
+            let command = RoomCommand::Interact { client, entity_id };
+            if let Err(e) = context.router.route(&client, command) {
+                send_error_msg("INTERACT message failed", e, context.clone(), &client).await;
+            }
+        }
+
+        Err(e) => {
+            debug!("Invalid message: {:?}", e);
+        }
+
+        _ => {
+            debug!("Not a command {}", String::from_utf8_lossy(&packet));
+
+            // Send the message back to the client to inform wrong format
+            let mes = format!(
+                "Not a command: {}\npacket: {:?}",
+                String::from_utf8_lossy(&packet),
+                &packet
+            );
+
+            if let Err(e) = send_packet(&context, mes.as_bytes(), client).await {
+                warn!("Can not send back the message to client: {}", e);
+            }
+        }
+    }
+}
+
+/////////////////////////////////////////////////////
+
+// Send message error to client
+async fn send_error_msg(
+    msg: &str,
+    e: Box<dyn Error + Send + Sync>,
+    context: Arc<ServerContext>,
+    client: &SocketAddr,
+) {
+    let error = format!("{msg}: {e}");
+    let error_msg = Message::Error(error.clone());
+
+    match send_packet(&context, &error_msg.serialize(), *client).await {
+        Ok(_) => {
+            debug!(%client, "Sent error message: {error}");
+        }
+
+        Err(e) => {
+            warn!(%client, "Can not send error message to player: {e}")
+        }
+    }
+}
+
+// Accept new player
+async fn accept_client(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    socket: usize,
+    player_name: &str,
+    offer: CompressionOffer,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    require_open(&context)?;
+    let compression = context.compressor.negotiate(offer);
+
+    // A repeated handshake may come from a restarted client with a different offer
+    let (player_id, is_new) = context.router.register(
+        client,
+        socket,
+        player_name,
+        compression,
+        context.settings().bandwidth,
+    );
+    Span::current().record("player", player_id);
+    if is_new {
+        info!(name = player_name, "Player joined the server");
+    }
+
+    let ack = Message::Ack(player_id, compression);
+    trace!(target: logging::PACKETS, message = ?ack, "Sending");
+    send_packet(&context, &ack.serialize(), client).await?;
+
+    let motd = context.settings().motd.clone();
+    if !motd.is_empty() {
+        send_packet(&context, &Message::Motd(motd).serialize(), client).await?;
+    }
+
+    Ok(())
+}
+
+// Remove player. Every way out of the server goes through here.
+async fn drop_player(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    reason: LeaveReason,
+) -> Result<(), Box<dyn Error + Sync + Send>> {
+    if let Some(session) = context.router.remove(&client) {
+        info!(?reason, "Player left the server");
+
+        // A quitting client already knows, anyone else gets told in case it is still there.
+        // Its session is gone, so it has to be named.
+        let mut recipients = context.router.addresses();
+        if reason != LeaveReason::Quit {
+            recipients.push(client);
+        }
+        context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Leave(session.id, reason).serialize(),
+            excluded_client: None,
+            recipients: Some(recipients),
+        })?;
+
+        // The room closes itself once its last player is gone
+        if let Some(room) = session.room.and_then(|id| context.router.room(id)) {
+            room.send(RoomCommand::Leave { client })?;
+        }
+
+        log_session_stats(&session);
+        context.reassembler(&client).forget(client);
+    }
+
+    Ok(())
+}
+
+// Nobody joins the server or starts a match once it is shutting down
+fn require_open(context: &ServerContext) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if context.shutdown.is_draining() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "Server is shutting down").into());
+    }
+    Ok(())
+}
+
+// Open a room on its own task with the client as its first player
+fn create_room(
+    context: Arc<ServerContext>,
+    client: SocketAddr,
+    room_name: RoomName,
+    password: RoomPass,
+    mode: GameMode,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    require_open(&context)?;
+    let player = new_player(&context, &client)?;
+
+    let settings = context.settings();
+    let room = context
+        .router
+        .open_room(settings.rooms.max_rooms, |room_id| {
+            let mut room = Room::new(
+                room_id,
+                room_name,
+                password,
+                mode,
+                settings.balance,
+                settings.modes.death_rules(mode),
+            );
+            room.interest = settings.interest;
+            room.lag = settings.lag;
+            room_actor::spawn(context.clone(), room)
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::QuotaExceeded, "Too many rooms"))?;
+
+    room.send(RoomCommand::Create { client, player })
+}
+
+// The room checks the password and answers the client itself
+fn join_room(
+    context: &ServerContext,
+    client: SocketAddr,
+    room_id: RoomId,
+    password: RoomPass,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    require_open(context)?;
+    let player = new_player(context, &client)?;
+
+    let room = context
+        .router
+        .room(room_id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Room not existed"))?;
+
+    room.send(RoomCommand::Join {
+        client,
+        player,
+        password,
+    })
+}
+
+// Every room a client enters starts them off fresh
+fn new_player(
+    context: &ServerContext,
+    client: &SocketAddr,
+) -> Result<Player, Box<dyn Error + Send + Sync>> {
+    context
+        .router
+        .with_session(client, |session| Player {
+            player_name: session.name.clone(),
+            ..Player::new(session.id)
+        })
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not registered").into())
+}
+
+/// Send ping to healthcheck
+async fn ping_sender(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(context.settings().liveness.ping_interval);
+
+    loop {
+        interval.tick().await;
+
+        let seq = context
+            .next_ping_seq
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        {
+            let mut pending = context.pending_pings.lock().await;
+            pending.push_back((seq, Instant::now()));
+            while pending.len() > globals::MAX_PENDING_PINGS {
+                pending.pop_front();
+            }
+        }
+
+        // Sent straight away rather than queued, so the wait for the next flush does not
+        // end up in the measured round trip
+        let ping = Message::Ping(seq, context.clock.now_ms()).serialize();
+        let pings: Vec<(Vec<u8>, SocketAddr)> = context
+            .router
+            .addresses()
+            .into_iter()
+            .map(|client| (ping.clone(), client))
+            .collect();
+
+        for (client, e) in send_all(&context, pings).await {
+            warn!(%client, "Failed to send PING: {}", e);
+        }
+    }
+}
+
+/// Drop players that have sent nothing valid within the inactivity timeout
+async fn cleanup_inactive(context: Arc<ServerContext>) {
+    let mut interval = tokio::time::interval(context.settings().liveness.scan_interval);
+
+    loop {
+        interval.tick().await;
+
+        let timeout = context.settings().liveness.inactivity_timeout;
+        for (addr, player_id) in context.router.inactive(timeout) {
+            let span = client_span(addr, Some(player_id));
+            async {
+                info!("Removing inactive client");
+
+                if let Err(e) = drop_player(context.clone(), addr, LeaveReason::Timeout).await {
+                    warn!("Failed to drop inactive client: {}", e);
+                }
+            }
+            .instrument(span)
+            .await;
+        }
+    }
+}
+
+// A pong for a ping we no longer remember is not a sample
+fn record_pong(context: &ServerContext, client: SocketAddr, sent_at: Option<Instant>) {
+    context.router.with_session(&client, |session| {
+        if let Some(sent_at) = sent_at {
+            session.rtt.add_sample(sent_at.elapsed());
+        }
+
+        trace!(
+            target: logging::PACKETS,
+            rtt = ?session.rtt.smoothed(),
+            jitter = ?session.rtt.jitter(),
+            "Received PONG"
+        );
+    });
+}
+
+fn log_session_stats(session: &Session) {
+    info!(
+        smoothed = ?session.rtt.smoothed(),
+        jitter = ?session.rtt.jitter(),
+        samples = session.rtt.samples(),
+        "Round trip"
+    );
+
+    let compression = session.compression_stats;
+    info!(
+        negotiated = ?session.compression,
+        ratio = compression.ratio(),
+        compressed = compression.compressed,
+        messages = compression.messages,
+        bytes_out = compression.bytes_out,
+        bytes_in = compression.bytes_in,
+        "Compression"
+    );
+
+    let bandwidth = session.bandwidth.stats();
+    info!(
+        rate = session.bandwidth.rate(),
+        loss = session.bandwidth.loss(),
+        snapshots_acked = bandwidth.snapshots_acked,
+        snapshots_lost = bandwidth.snapshots_lost,
+        bytes_sent = bandwidth.bytes_sent,
+        bytes_acked = bandwidth.bytes_acked,
+        "Bandwidth"
+    );
+}
+
+fn buffer_inputs(
+    context: &ServerContext,
+    client: SocketAddr,
+    player_id: PlayerID,
+    commands: Vec<InputCommand>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if commands.len() > globals::MAX_INPUTS_PER_PACKET {
+        return Err(
+            io::Error::new(io::ErrorKind::InvalidInput, "Too many inputs in one packet").into(),
+        );
+    }
+
+    let finite = commands.iter().all(|command| match command.action {
+        InputAction::Move(x, y) | InputAction::Shoot(x, y) => x.is_finite() && y.is_finite(),
+    });
+    if !finite {
+        return Err(
+            io::Error::new(io::ErrorKind::InvalidInput, "Input is not a finite vector").into(),
+        );
+    }
+
+    let session_id = context
+        .router
+        .with_session(&client, |session| session.id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not registered"))?;
+
+    // Never trust the id in the packet over the address it came from
+    if session_id != player_id {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Player id mismatch").into());
+    }
+
+    // Applied in order by the room tick
+    context.router.route(
+        &client,
+        RoomCommand::Inputs {
+            client,
+            commands,
+            received_at: Instant::now(),
+        },
+    )
+}
@@ -0,0 +1,124 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+/// A datagram as it came off the socket
+pub struct Datagram {
+    pub client: SocketAddr,
+    /// Index of the socket it arrived on, replies go out the same way
+    pub socket: usize,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReceiveStats {
+    pub received: u64,
+    /// Dropped because the client's worker was too far behind
+    pub dropped: u64,
+    /// Deepest any queue got since the last call
+    pub peak_depth: usize,
+    /// Current depth of each worker's queue
+    pub depths: Vec<usize>,
+}
+
+/// Spreads received datagrams over a fixed set of workers with bounded queues. Every
+/// address hashes to one worker so a client's packets are handled in order, and a client
+/// flooding its worker only loses its own packets and those of its neighbours.
+pub struct ReceivePipeline {
+    queues: Vec<mpsc::Sender<Datagram>>,
+    hasher: RandomState,
+    buffers: Mutex<Vec<Vec<u8>>>,
+    pool_size: usize,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    peak_depth: AtomicUsize,
+}
+
+impl ReceivePipeline {
+    /// The pipeline and one receiver per worker
+    pub fn new(
+        workers: usize,
+        depth: usize,
+        pool_size: usize,
+    ) -> (Self, Vec<mpsc::Receiver<Datagram>>) {
+        let (queues, receivers) = (0..workers.max(1))
+            .map(|_| mpsc::channel(depth.max(1)))
+            .unzip();
+
+        let pipeline = ReceivePipeline {
+            queues,
+            hasher: RandomState::new(),
+            buffers: Mutex::new(Vec::with_capacity(pool_size)),
+            pool_size,
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            peak_depth: AtomicUsize::new(0),
+        };
+        (pipeline, receivers)
+    }
+
+    /// An empty buffer, reusing one a worker is done with when there is one
+    pub fn buffer(&self) -> Vec<u8> {
+        self.buffers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_default()
+    }
+
+    pub fn recycle(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        if buffers.len() < self.pool_size {
+            buffers.push(buffer);
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.queues.len()
+    }
+
+    /// Index of the worker that handles everything from a client
+    pub fn worker(&self, client: &SocketAddr) -> usize {
+        self.hasher.hash_one(client) as usize % self.queues.len()
+    }
+
+    /// Queue a datagram for its client's worker. Never waits: a full queue drops it.
+    pub fn dispatch(&self, datagram: Datagram) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+
+        let queue = &self.queues[self.worker(&datagram.client)];
+
+        match queue.try_send(datagram) {
+            Ok(()) => {
+                let depth = queue.max_capacity() - queue.capacity();
+                self.peak_depth.fetch_max(depth, Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(datagram)) | Err(TrySendError::Closed(datagram)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                self.recycle(datagram.payload);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ReceiveStats {
+        ReceiveStats {
+            received: self.received.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            peak_depth: self.peak_depth.swap(0, Ordering::Relaxed),
+            depths: self
+                .queues
+                .iter()
+                .map(|queue| queue.max_capacity() - queue.capacity())
+                .collect(),
+        }
+    }
+}
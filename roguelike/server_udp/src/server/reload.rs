@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use tracing::{Instrument, info, warn};
+
+use crate::{
+    config::settings::{ServerSettings, SettingChange},
+    game::player::LeaveReason,
+    logging,
+    network::message::Message,
+};
+
+use super::{BroadcastMessage, ServerContext, client_span, drop_player};
+
+/// Swap in new settings if everything they change can change while running. Returns
+/// what changed, or an error naming the settings that need a restart.
+pub(super) async fn apply(
+    context: &Arc<ServerContext>,
+    settings: ServerSettings,
+) -> Result<Vec<SettingChange>, String> {
+    settings.validate()?;
+
+    let old = context.settings();
+    let changes = old.changes(&settings);
+    let restart: Vec<String> = changes
+        .iter()
+        .filter(|change| change.needs_restart)
+        .map(|change| format!("{} ({} -> {})", change.key, change.old, change.new))
+        .collect();
+    if !restart.is_empty() {
+        return Err(format!(
+            "restart the server to change {}",
+            restart.join(", ")
+        ));
+    }
+    if changes.is_empty() {
+        return Ok(changes);
+    }
+
+    let settings = Arc::new(settings);
+    *context.settings.write().unwrap_or_else(|e| e.into_inner()) = settings.clone();
+
+    // Everything else reads the settings as it needs them
+    context.router.set_bandwidth(settings.bandwidth);
+    for reassembler in context.reassemblers.iter() {
+        reassembler
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .set_settings(settings.fragment);
+    }
+    if settings.logging.filter != old.logging.filter
+        && let Err(e) = logging::set_filter(&settings.logging.filter)
+    {
+        warn!("{e}");
+    }
+
+    for addr in context.router.addresses() {
+        if settings.banned.contains(&addr.ip()) {
+            let player = context.router.with_session(&addr, |session| session.id);
+            async {
+                info!("Kicking banned client");
+                if let Err(e) = drop_player(context.clone(), addr, LeaveReason::Kicked).await {
+                    warn!("Failed to kick banned client: {}", e);
+                }
+            }
+            .instrument(client_span(addr, player))
+            .await;
+        }
+    }
+
+    if settings.motd != old.motd && !settings.motd.is_empty() {
+        if let Err(e) = context.broadcast_tx.send(BroadcastMessage {
+            msg: Message::Motd(settings.motd.clone()).serialize(),
+            excluded_client: None,
+            recipients: None,
+        }) {
+            warn!("Failed to queue MOTD: {}", e);
+        }
+        context.flush_queues.notify_one();
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,611 @@
+use std::{error::Error, io, net::SocketAddr, sync::Arc, time::Instant};
+
+use tokio::{sync::mpsc, time::MissedTickBehavior};
+use tracing::{Instrument, Span, debug, info, info_span, trace, warn};
+
+use crate::{
+    config::globals::{
+        self,
+        commands::{CREATE_ROOM, JOIN_ROOM},
+    },
+    game::{
+        entity::{EntityId, EntityKind},
+        input::InputCommand,
+        interest::ClientUpdate,
+        inventory::InventoryError,
+        item::{self, ConsumableEffect, ItemKind, items},
+        player::Player,
+        room::{Room, RoomPass},
+    },
+    logging,
+    network::message::Message,
+};
+
+use super::{BroadcastMessage, ServerContext, client_span, send_error_msg, send_packet};
+
+/// Everything a client can ask of the room it is in
+pub enum RoomCommand {
+    /// First player of a room that was just opened for them
+    Create {
+        client: SocketAddr,
+        player: Player,
+    },
+    Join {
+        client: SocketAddr,
+        player: Player,
+        password: RoomPass,
+    },
+    Leave {
+        client: SocketAddr,
+    },
+    Inputs {
+        client: SocketAddr,
+        commands: Vec<InputCommand>,
+        received_at: Instant,
+    },
+    UseItem {
+        client: SocketAddr,
+        slot: u8,
+    },
+    DropItem {
+        client: SocketAddr,
+        slot: u8,
+        count: u16,
+    },
+    Equip {
+        client: SocketAddr,
+        slot: u8,
+    },
+    Interact {
+        client: SocketAddr,
+        entity_id: EntityId,
+    },
+    VoteDescend {
+        client: SocketAddr,
+    },
+}
+
+impl RoomCommand {
+    fn client(&self) -> SocketAddr {
+        match self {
+            RoomCommand::Create { client, .. }
+            | RoomCommand::Join { client, .. }
+            | RoomCommand::Leave { client }
+            | RoomCommand::Inputs { client, .. }
+            | RoomCommand::UseItem { client, .. }
+            | RoomCommand::DropItem { client, .. }
+            | RoomCommand::Equip { client, .. }
+            | RoomCommand::Interact { client, .. }
+            | RoomCommand::VoteDescend { client } => *client,
+        }
+    }
+
+    /// Players on their way in are not in the room yet
+    fn span(&self, room: &Room) -> Span {
+        let client = self.client();
+        let player = match self {
+            RoomCommand::Create { player, .. } | RoomCommand::Join { player, .. } => {
+                Some(player.id)
+            }
+            _ => room.players.get(&client).map(|player| player.id),
+        };
+        client_span(client, player)
+    }
+}
+
+/// Where to send commands for a running room
+#[derive(Clone)]
+pub struct RoomHandle {
+    commands: mpsc::UnboundedSender<RoomCommand>,
+}
+
+impl RoomHandle {
+    pub fn send(&self, command: RoomCommand) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.commands
+            .send(command)
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "Room not existed").into())
+    }
+}
+
+/// Run a room on its own task. The task owns the room, so nothing else ever locks it.
+pub fn spawn(context: Arc<ServerContext>, room: Room) -> RoomHandle {
+    let (tx, rx) = mpsc::unbounded_channel();
+    // Rooms outlive the request that opened them
+    let span = info_span!(parent: None, "room", room = room.id);
+    context
+        .shutdown
+        .spawn_room(run(context.clone(), room, rx).instrument(span));
+    RoomHandle { commands: tx }
+}
+
+/// Tick at a fixed rate and handle commands in between, until the last player leaves or
+/// the server stops
+async fn run(
+    context: Arc<ServerContext>,
+    mut room: Room,
+    mut commands: mpsc::UnboundedReceiver<RoomCommand>,
+) {
+    // Tick in step with the server clock so snapshots carry the tick a PONG reports
+    let mut interval = tokio::time::interval_at(
+        context.clock.next_mid_tick().into(),
+        context.clock.tick_interval(),
+    );
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut last_tick = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let tick = context.clock.tick();
+                if tick == room.tick {
+                    continue;
+                }
+
+                let now = Instant::now();
+                let dt = now.duration_since(last_tick).as_secs_f32();
+                last_tick = now;
+
+                tick_room(&context, &mut room, tick, dt);
+            }
+
+            command = commands.recv() => {
+                let Some(command) = command else {
+                    break;
+                };
+                let span = command.span(&room);
+                handle_command(&context, &mut room, command)
+                    .instrument(span)
+                    .await;
+
+                if room.players.is_empty() {
+                    break;
+                }
+            }
+
+            // The grace period is over and the run is not, tell the party how far it got
+            _ = context.shutdown.closing_rooms().cancelled() => {
+                if let Some(event) = room.interrupt() {
+                    info!(depth = room.run.depth, "Run interrupted");
+                    broadcast_to_room(&context, &room, Message::from(event).serialize(), None);
+                    context.flush_queues.notify_one();
+                }
+                break;
+            }
+        }
+    }
+
+    context.router.close_room(room.id);
+
+    // Anyone who tried to join while the room was closing has to be told
+    commands.close();
+    while let Some(command) = commands.recv().await {
+        if let RoomCommand::Join { client, .. } = command {
+            let error = io::Error::new(io::ErrorKind::NotFound, "Room not existed");
+            send_error_msg(
+                "Join room failed",
+                Box::new(error),
+                context.clone(),
+                &client,
+            )
+            .await;
+        }
+    }
+
+    info!("Room closed");
+}
+
+/// Advance the room by one tick and queue what every member should hear about
+fn tick_room(context: &ServerContext, room: &mut Room, tick: u32, dt: f32) {
+    // Picks up reloaded settings
+    let settings = context.settings();
+    room.interest = settings.interest;
+    room.balance = settings.balance;
+    room.lag = settings.lag;
+    room.death_rules = settings.modes.death_rules(room.mode);
+
+    // Lag compensation rewinds by the shooter's latest round trip
+    let round_trips = context.router.round_trips(room.players.keys());
+    for (addr, player) in room.players.iter_mut() {
+        if let Some(rtt) = round_trips.get(addr) {
+            player.rtt = *rtt;
+        }
+    }
+
+    for event in room.tick(tick, dt) {
+        broadcast_to_room(context, room, Message::from(event).serialize(), None);
+    }
+
+    let budgets = context
+        .router
+        .snapshot_budgets(room.players.keys(), room.tick, Instant::now());
+    for (client, update) in room.client_updates(&budgets) {
+        send_client_update(context, client, update);
+    }
+
+    // Everything for this tick is queued, send it
+    context.flush_queues.notify_one();
+}
+
+async fn handle_command(context: &Arc<ServerContext>, room: &mut Room, command: RoomCommand) {
+    let (client, result, error_msg) = match command {
+        RoomCommand::Create { client, player } => (
+            client,
+            admit(context, room, client, player, true).await,
+            "Create room failed",
+        ),
+
+        RoomCommand::Join {
+            client,
+            player,
+            password,
+        } => {
+            let full = !room.players.contains_key(&client)
+                && room.players.len() >= context.settings().rooms.max_players;
+            let result = if room.room_pass != password {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect password").into())
+            } else if full {
+                Err(io::Error::new(io::ErrorKind::QuotaExceeded, "Room is full").into())
+            } else {
+                admit(context, room, client, player, false).await
+            };
+            (client, result, "Join room failed")
+        }
+
+        RoomCommand::Leave { client } => {
+            if let Some(player) = room.remove_player(&client) {
+                info!("Player left the room");
+                log_input_stats(&player);
+            }
+            return;
+        }
+
+        RoomCommand::Inputs {
+            client,
+            commands,
+            received_at,
+        } => (
+            client,
+            member(room, &client).map(|player| player.inputs.receive(commands, received_at)),
+            "PLAYER_INPUT message failed",
+        ),
+
+        RoomCommand::UseItem { client, slot } => (
+            client,
+            use_item(context, room, client, slot).await,
+            "USE_ITEM message failed",
+        ),
+
+        RoomCommand::DropItem {
+            client,
+            slot,
+            count,
+        } => (
+            client,
+            drop_item(context, room, client, slot, count).await,
+            "DROP_ITEM message failed",
+        ),
+
+        RoomCommand::Equip { client, slot } => (
+            client,
+            equip_item(context, room, client, slot).await,
+            "EQUIP message failed",
+        ),
+
+        RoomCommand::Interact { client, entity_id } => (
+            client,
+            interact(context, room, client, entity_id).await,
+            "INTERACT message failed",
+        ),
+
+        RoomCommand::VoteDescend { client } => (
+            client,
+            vote_descend(room, client),
+            "VOTE_DESCEND message failed",
+        ),
+    };
+
+    if let Err(e) = result {
+        send_error_msg(error_msg, e, context.clone(), &client).await;
+    }
+}
+
+// Put a player in the room and tell them where they are. A client moving over from
+// another room is taken out of that one.
+async fn admit(
+    context: &ServerContext,
+    room: &mut Room,
+    client: SocketAddr,
+    player: Player,
+    created: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let player_id = player.id;
+
+    // A member joining again keeps their player as it is, dead or alive, and is only
+    // told where they are again
+    let rejoined = room.players.contains_key(&client);
+    if !rejoined {
+        room.add_player(client, player);
+
+        match context.router.enter_room(&client, room.id) {
+            // Disconnected while the command was on its way
+            None => {
+                room.remove_player(&client);
+                return Err(
+                    io::Error::new(io::ErrorKind::NotFound, "Client not registered").into(),
+                );
+            }
+            Some(Some(previous)) if previous != room.id => {
+                if let Some(previous) = context.router.room(previous) {
+                    let _ = previous.send(RoomCommand::Leave { client });
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    if created {
+        send_room_state(context, room, &client).await;
+
+        let mut response = vec![CREATE_ROOM];
+        response.extend_from_slice(&room.id.to_le_bytes());
+        send_packet(context, &response, client).await?;
+
+        info!(name = %room.room_name, mode = ?room.mode, "Created room");
+    } else {
+        let mut response = vec![JOIN_ROOM];
+        let room_name_bytes = room.room_name.as_bytes();
+        response.extend_from_slice(&(room_name_bytes.len() as u32).to_le_bytes());
+        response.extend_from_slice(room_name_bytes);
+
+        if let Err(e) = send_packet(context, &response, client).await {
+            warn!("Cannot add player {} to the room: {}", player_id, e);
+            return Err(e.into());
+        }
+
+        if !rejoined {
+            info!("Player joined the room");
+        }
+        send_room_state(context, room, &client).await;
+    }
+
+    Ok(())
+}
+
+// Queue a message for every player in the room
+fn broadcast_to_room(
+    context: &ServerContext,
+    room: &Room,
+    msg: Vec<u8>,
+    excluded_client: Option<SocketAddr>,
+) {
+    let recipients = room.players.keys().copied().collect();
+
+    if let Err(e) = context.broadcast_tx.send(BroadcastMessage {
+        msg,
+        excluded_client,
+        recipients: Some(recipients),
+    }) {
+        warn!("Failed to queue broadcast: {}", e);
+    }
+}
+
+/// Queue everything one client should hear about after a tick, in the order it needs it
+fn send_client_update(context: &ServerContext, client: SocketAddr, update: ClientUpdate) {
+    let messages = update
+        .despawned_actors
+        .into_iter()
+        .map(Message::ActorDespawn)
+        .chain(
+            update
+                .despawned_entities
+                .into_iter()
+                .map(Message::EntityDespawn),
+        )
+        .chain(update.spawned_actors.into_iter().map(Message::ActorSpawn))
+        .chain(
+            update
+                .spawned_entities
+                .into_iter()
+                .map(Message::EntitySpawn),
+        )
+        .chain(std::iter::once(Message::RoomSnapshot(update.snapshot)));
+
+    for message in messages {
+        if let Err(e) = context.broadcast_tx.send(BroadcastMessage {
+            msg: message.serialize(),
+            excluded_client: None,
+            recipients: Some(vec![client]),
+        }) {
+            warn!(%client, "Failed to queue update: {}", e);
+        }
+    }
+}
+
+// Tell a player who just entered a room which floor the party is on. What is on it
+// arrives through the player's view on the next tick.
+async fn send_room_state(context: &ServerContext, room: &Room, client: &SocketAddr) {
+    let floor_msg = Message::from(room.floor_changed_event()).serialize();
+    if let Err(e) = send_packet(context, &floor_msg, *client).await {
+        warn!(%client, "Failed to send floor: {}", e);
+    }
+}
+
+// Inventory changes are only ever replicated to the owner
+async fn send_inventory(
+    context: &ServerContext,
+    client: &SocketAddr,
+    player: &Player,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let msg = Message::Inventory(player.inventory.clone()).serialize();
+    send_packet(context, &msg, *client).await?;
+
+    trace!(target: logging::PACKETS, %client, "Sent inventory");
+    Ok(())
+}
+
+fn log_input_stats(player: &Player) {
+    let stats = player.inputs.stats();
+    info!(
+        applied = stats.applied,
+        duplicates = stats.duplicates,
+        late = stats.late,
+        dropped = stats.dropped,
+        rejected = stats.rejected,
+        "Inputs"
+    );
+}
+
+fn member<'a>(
+    room: &'a mut Room,
+    client: &SocketAddr,
+) -> Result<&'a mut Player, Box<dyn Error + Send + Sync>> {
+    room.players.get_mut(client).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Player is not in a room").into()
+    })
+}
+
+// Downed players and spectators can't act
+fn require_alive(player: &Player) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !player.is_alive() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Player is not alive").into());
+    }
+    Ok(())
+}
+
+async fn use_item(
+    context: &ServerContext,
+    room: &mut Room,
+    client: SocketAddr,
+    slot: u8,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let player = member(room, &client)?;
+    require_alive(player)?;
+
+    let stack = *player.inventory.get(slot)?;
+    let def = item::item_def(stack.item).ok_or(InventoryError::UnknownItem(stack.item))?;
+
+    match def.kind {
+        ItemKind::Consumable(ConsumableEffect::Heal(amount)) => {
+            player.inventory.remove(slot, 1)?;
+            player.heal(amount);
+            debug!("Player {} used {}", player.id, def.name);
+        }
+        _ => return Err(InventoryError::NotUsable(slot).into()),
+    }
+
+    send_inventory(context, &client, player).await
+}
+
+async fn equip_item(
+    context: &ServerContext,
+    room: &mut Room,
+    client: SocketAddr,
+    slot: u8,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let player = member(room, &client)?;
+    require_alive(player)?;
+
+    player.inventory.equip(slot)?;
+
+    send_inventory(context, &client, player).await
+}
+
+async fn drop_item(
+    context: &ServerContext,
+    room: &mut Room,
+    client: SocketAddr,
+    slot: u8,
+    count: u16,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let player = member(room, &client)?;
+    require_alive(player)?;
+
+    let stack = player.inventory.remove(slot, count)?;
+    let position = player.position;
+    room.spawn_pickup(stack, position);
+
+    send_inventory(context, &client, member(room, &client)?).await
+}
+
+async fn interact(
+    context: &ServerContext,
+    room: &mut Room,
+    client: SocketAddr,
+    entity_id: EntityId,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let entity = room
+        .entities
+        .get(&entity_id)
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Entity not existed"))?;
+
+    let player = member(room, &client)?;
+    require_alive(player)?;
+
+    if player.position.distance_to(&entity.position) > globals::INTERACT_RADIUS {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Entity is out of reach").into());
+    }
+    let player_id = player.id;
+
+    match entity.kind {
+        EntityKind::Pickup(stack) => {
+            let leftover = player.inventory.add(stack)?;
+
+            match leftover {
+                // Only part of the stack fit, the rest stays on the map
+                Some(leftover) => {
+                    let mut remaining = entity.clone();
+                    remaining.kind = EntityKind::Pickup(leftover);
+                    room.entities.insert(entity_id, remaining);
+                    room.mark_changed(entity_id);
+                }
+                None => {
+                    room.take_pickup(entity_id);
+                }
+            }
+
+            debug!("Player {} picked up entity {}", player_id, entity_id);
+        }
+
+        EntityKind::Chest { opened: true, .. } => {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidInput, "Chest is already open").into(),
+            );
+        }
+
+        EntityKind::Chest { locked, .. } => {
+            if locked {
+                let key_slot = player.inventory.find(items::BRONZE_KEY).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Chest is locked")
+                })?;
+                player.inventory.remove(key_slot, 1)?;
+            }
+
+            // Viewers pick up the opened chest and its drops on the next tick
+            room.open_chest(entity_id);
+
+            debug!("Player {} opened chest {}", player_id, entity_id);
+        }
+
+        EntityKind::Stairs => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Stand on the stairs or send VOTE_DESCEND to move on",
+            )
+            .into());
+        }
+    }
+
+    send_inventory(context, &client, member(room, &client)?).await
+}
+
+fn vote_descend(room: &mut Room, client: SocketAddr) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let player = member(room, &client)?;
+    require_alive(player)?;
+    let player_id = player.id;
+
+    room.vote_descend(player_id);
+    debug!("Player {} voted to descend", player_id);
+
+    Ok(())
+}
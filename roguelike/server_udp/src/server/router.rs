@@ -0,0 +1,279 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    io,
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::{
+    config::settings::BandwidthSettings,
+    game::{
+        player::{PlayerID, PlayerName},
+        room::RoomId,
+    },
+    network::{
+        bandwidth::BandwidthEstimator,
+        compress::{Compression, CompressionStats},
+        rtt::RttEstimator,
+    },
+};
+
+use super::room_actor::{RoomCommand, RoomHandle};
+
+/// The network side of a connected client. Its game state lives in the room it is in.
+pub struct Session {
+    pub id: PlayerID,
+    pub name: PlayerName,
+    pub last_active: Instant,
+    /// Server socket the client last sent to, also the one it is answered from
+    pub socket: usize,
+    /// Round trip time from answered pings, zero until measured
+    pub rtt: RttEstimator,
+    /// Picked in the handshake, used for everything queued to this client
+    pub compression: Compression,
+    pub compression_stats: CompressionStats,
+    /// Send rate this client can take, decides how much of each snapshot it gets
+    pub bandwidth: BandwidthEstimator,
+    pub room: Option<RoomId>,
+}
+
+/// Hands out ids that are not in use, circling back to 1 after u32::MAX
+struct IdPool {
+    next: u32,
+    active: HashSet<u32>,
+}
+
+impl IdPool {
+    fn new() -> Self {
+        IdPool {
+            next: 1,
+            active: HashSet::new(),
+        }
+    }
+
+    fn assign(&mut self) -> u32 {
+        let mut id = self.next;
+        while self.active.contains(&id) {
+            id = id.wrapping_add(1).max(1);
+        }
+
+        self.active.insert(id);
+        self.next = id.wrapping_add(1).max(1);
+        id
+    }
+
+    fn free(&mut self, id: u32) {
+        self.active.remove(&id);
+    }
+}
+
+struct Sessions {
+    by_addr: HashMap<SocketAddr, Session>,
+    ids: IdPool,
+}
+
+struct Rooms {
+    handles: HashMap<RoomId, RoomHandle>,
+    ids: IdPool,
+}
+
+/// Finds the session and room behind every address. Both locks are only held for a lookup
+/// or an update, never across an await and never while taking the other, so handlers can't
+/// deadlock on them and rooms never wait on each other.
+pub struct Router {
+    sessions: Mutex<Sessions>,
+    rooms: Mutex<Rooms>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router {
+            sessions: Mutex::new(Sessions {
+                by_addr: HashMap::new(),
+                ids: IdPool::new(),
+            }),
+            rooms: Mutex::new(Rooms {
+                handles: HashMap::new(),
+                ids: IdPool::new(),
+            }),
+        }
+    }
+
+    // A handler that panicked mid-update leaves nothing half written worth refusing over
+    fn sessions(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn rooms(&self) -> MutexGuard<'_, Rooms> {
+        self.rooms.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Open a session for a new client, or update the compression of an existing one.
+    /// Returns the client's id and whether it is new.
+    pub fn register(
+        &self,
+        addr: SocketAddr,
+        socket: usize,
+        name: &str,
+        compression: Compression,
+        bandwidth: BandwidthSettings,
+    ) -> (PlayerID, bool) {
+        let mut sessions = self.sessions();
+
+        if let Some(session) = sessions.by_addr.get_mut(&addr) {
+            session.compression = compression;
+            session.socket = socket;
+            return (session.id, false);
+        }
+
+        let id = sessions.ids.assign();
+        sessions.by_addr.insert(
+            addr,
+            Session {
+                id,
+                name: name.to_string(),
+                last_active: Instant::now(),
+                socket,
+                rtt: RttEstimator::default(),
+                compression,
+                compression_stats: CompressionStats::default(),
+                bandwidth: BandwidthEstimator::new(bandwidth),
+                room: None,
+            },
+        );
+        (id, true)
+    }
+
+    /// Close a session and free its id
+    pub fn remove(&self, addr: &SocketAddr) -> Option<Session> {
+        let mut sessions = self.sessions();
+        let session = sessions.by_addr.remove(addr)?;
+        sessions.ids.free(session.id);
+        debug!("UserID: {} is freed", session.id);
+        Some(session)
+    }
+
+    pub fn with_session<R>(
+        &self,
+        addr: &SocketAddr,
+        f: impl FnOnce(&mut Session) -> R,
+    ) -> Option<R> {
+        self.sessions().by_addr.get_mut(addr).map(f)
+    }
+
+    pub fn contains(&self, addr: &SocketAddr) -> bool {
+        self.sessions().by_addr.contains_key(addr)
+    }
+
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        self.sessions().by_addr.keys().copied().collect()
+    }
+
+    /// New send rate limits for every session, from a settings reload
+    pub fn set_bandwidth(&self, settings: BandwidthSettings) {
+        for session in self.sessions().by_addr.values_mut() {
+            session.bandwidth.set_settings(settings);
+        }
+    }
+
+    /// Clients that have sent nothing valid for longer than `timeout`
+    pub fn inactive(&self, timeout: Duration) -> Vec<(SocketAddr, PlayerID)> {
+        self.sessions()
+            .by_addr
+            .iter()
+            .filter(|(_, session)| session.last_active.elapsed() > timeout)
+            .map(|(addr, session)| (*addr, session.id))
+            .collect()
+    }
+
+    /// Move a client into `room_id`, returning the room it was in before. None when the
+    /// client is no longer connected.
+    pub fn enter_room(&self, addr: &SocketAddr, room_id: RoomId) -> Option<Option<RoomId>> {
+        self.with_session(addr, |session| session.room.replace(room_id))
+    }
+
+    /// Latest round trip time of each client
+    pub fn round_trips<'a>(
+        &self,
+        addrs: impl Iterator<Item = &'a SocketAddr>,
+    ) -> HashMap<SocketAddr, Duration> {
+        let sessions = self.sessions();
+        addrs
+            .filter_map(|addr| Some((*addr, sessions.by_addr.get(addr)?.rtt.smoothed())))
+            .collect()
+    }
+
+    /// Note snapshot `tick` going out to each client and return the bytes it may use
+    pub fn snapshot_budgets<'a>(
+        &self,
+        addrs: impl Iterator<Item = &'a SocketAddr>,
+        tick: u32,
+        now: Instant,
+    ) -> HashMap<SocketAddr, usize> {
+        let mut sessions = self.sessions();
+        addrs
+            .filter_map(|addr| {
+                let session = sessions.by_addr.get_mut(addr)?;
+                Some((*addr, session.bandwidth.next_snapshot(tick, now)))
+            })
+            .collect()
+    }
+
+    /// Give a new room an id and keep its handle for routing, unless `limit` rooms are
+    /// already open
+    pub fn open_room(
+        &self,
+        limit: usize,
+        start: impl FnOnce(RoomId) -> RoomHandle,
+    ) -> Option<RoomHandle> {
+        let mut rooms = self.rooms();
+        if rooms.handles.len() >= limit {
+            return None;
+        }
+
+        let id = rooms.ids.assign();
+        let handle = start(id);
+        rooms.handles.insert(id, handle.clone());
+        Some(handle)
+    }
+
+    pub fn close_room(&self, room_id: RoomId) {
+        let mut rooms = self.rooms();
+        if rooms.handles.remove(&room_id).is_some() {
+            rooms.ids.free(room_id);
+            debug!("RoomID: {} is freed", room_id);
+        }
+    }
+
+    pub fn room(&self, room_id: RoomId) -> Option<RoomHandle> {
+        self.rooms().handles.get(&room_id).cloned()
+    }
+
+    /// Hand a command to the room the client is in
+    pub fn route(
+        &self,
+        addr: &SocketAddr,
+        command: RoomCommand,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let room_id = self
+            .with_session(addr, |session| session.room)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Client not registered"))?
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "Player is not in a room")
+            })?;
+
+        self.room(room_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Room not existed"))?
+            .send(command)
+    }
+}
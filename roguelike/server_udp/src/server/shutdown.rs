@@ -0,0 +1,160 @@
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use tokio::time::{Instant, timeout};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+use crate::{config::globals, network::message::Message};
+
+use super::{BroadcastMessage, ServerContext, send_all};
+
+/// Owns every task the server spawns, so stopping the server stops all of them
+pub struct Shutdown {
+    draining: AtomicBool,
+    /// Cancelled last, every task stops
+    stop: CancellationToken,
+    /// Cancelled once the grace period is over, rooms end their runs
+    close_rooms: CancellationToken,
+    tasks: TaskTracker,
+    rooms: TaskTracker,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let stop = CancellationToken::new();
+        Shutdown {
+            draining: AtomicBool::new(false),
+            close_rooms: stop.child_token(),
+            stop,
+            tasks: TaskTracker::new(),
+            rooms: TaskTracker::new(),
+        }
+    }
+
+    /// Set once a shutdown has begun, nobody new is let in after that
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Fires when every task has to stop
+    pub fn stopping(&self) -> &CancellationToken {
+        &self.stop
+    }
+
+    /// Fires when rooms still running at the end of the grace period have to wrap up
+    pub fn closing_rooms(&self) -> &CancellationToken {
+        &self.close_rooms
+    }
+
+    /// Spawn a task that is dropped wherever it is when the server stops
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let stop = self.stop.clone();
+        self.tasks.spawn(async move {
+            tokio::select! {
+                _ = stop.cancelled() => {}
+                _ = task => {}
+            }
+        });
+    }
+
+    /// Spawn a task that watches `stopping` itself, to finish what it was doing first
+    pub fn spawn_graceful<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+
+    /// Spawn a room actor, which watches `closing_rooms`
+    pub fn spawn_room<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.rooms.spawn(task);
+    }
+}
+
+/// Stop letting players in, count down while rooms finish their runs, then stop every task
+pub(super) async fn coordinate(context: &ServerContext, reason: &str) {
+    let shutdown = &context.shutdown;
+    if shutdown.draining.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let grace = context.settings().shutdown_grace;
+    info!(reason, ?grace, "Shutting down, waiting for rooms to finish");
+
+    // Rooms close by themselves once their last player leaves
+    shutdown.rooms.close();
+    let deadline = Instant::now() + grace;
+    let mut notices = tokio::time::interval(globals::SHUTDOWN_NOTICE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = shutdown.rooms.wait() => break,
+            _ = tokio::time::sleep_until(deadline) => break,
+            _ = notices.tick() => {
+                announce(context, reason, deadline.saturating_duration_since(Instant::now()));
+            }
+        }
+    }
+
+    shutdown.close_rooms.cancel();
+    if timeout(globals::TASK_STOP_TIMEOUT, shutdown.rooms.wait())
+        .await
+        .is_err()
+    {
+        warn!("Rooms still running after {:?}", globals::TASK_STOP_TIMEOUT);
+    }
+
+    // The broadcast handler sends what the rooms queued on its way out
+    shutdown.stop.cancel();
+    shutdown.tasks.close();
+    if timeout(globals::TASK_STOP_TIMEOUT, shutdown.tasks.wait())
+        .await
+        .is_err()
+    {
+        warn!("Tasks still running after {:?}", globals::TASK_STOP_TIMEOUT);
+    }
+
+    // Sent straight out, nothing is left to flush a queue
+    let notice = Message::ServerShutdown(reason.to_string(), 0).serialize();
+    let packets = context
+        .router
+        .addresses()
+        .into_iter()
+        .map(|client| (notice.clone(), client))
+        .collect();
+    for (client, e) in send_all(context, packets).await {
+        warn!(%client, "Failed to send SERVER_SHUTDOWN: {}", e);
+    }
+
+    info!("Server stopped");
+}
+
+/// Tell every client how long until the server stops
+fn announce(context: &ServerContext, reason: &str, left: Duration) {
+    let countdown_secs = left.as_secs_f64().ceil() as u16;
+    let msg = Message::ServerShutdown(reason.to_string(), countdown_secs).serialize();
+
+    if let Err(e) = context.broadcast_tx.send(BroadcastMessage {
+        msg,
+        excluded_client: None,
+        recipients: None,
+    }) {
+        warn!("Failed to queue SERVER_SHUTDOWN: {}", e);
+    }
+    context.flush_queues.notify_one();
+}
@@ -1 +1,5 @@
-
+/// True if sequence `a` comes after `b`, treating the u32 space as a ring so numbering
+/// can wrap around without a long-lived client getting stuck
+pub fn sequence_newer(a: u32, b: u32) -> bool {
+    a != b && a.wrapping_sub(b) < u32::MAX / 2
+}
@@ -0,0 +1,61 @@
+use std::{collections::HashMap, net::SocketAddr, thread, time::Instant};
+
+use server_udp::{
+    game::{
+        floor::Balance,
+        mode::{GameMode, ModeSettings},
+        player::Player,
+        room::Room,
+    },
+    network::{message::Message, rtt::ServerClock},
+};
+
+// Slow enough that half a tick covers a sleep overshooting on a busy machine
+const TICK_RATE_HZ: u64 = 10;
+
+fn sleep_until(deadline: Instant) {
+    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+}
+
+#[test]
+fn timer_from_mid_tick_sees_every_tick_once() {
+    let clock = ServerClock::new(TICK_RATE_HZ);
+    let start = clock.next_mid_tick();
+    sleep_until(start);
+    let first = clock.tick();
+
+    for ticks in 1..6 {
+        sleep_until(start + clock.tick_interval() * ticks);
+        assert_eq!(clock.tick(), first + ticks);
+    }
+}
+
+#[test]
+fn snapshots_carry_the_tick_a_pong_reports() {
+    let clock = ServerClock::new(TICK_RATE_HZ);
+    let client = SocketAddr::from(([127, 0, 0, 1], 1));
+    let mut room = Room::new(
+        1,
+        "room".into(),
+        String::new(),
+        GameMode::Standard,
+        Balance::default(),
+        ModeSettings::default().standard,
+    );
+    room.add_player(client, Player::new(1));
+
+    let start = clock.next_mid_tick();
+    for ticks in 0..3 {
+        sleep_until(start + clock.tick_interval() * ticks);
+
+        // What the room actor and the PONG handler each read off the clock
+        room.tick(clock.tick(), clock.tick_interval().as_secs_f32());
+        let pong = Message::Pong(1, 0, clock.now_ms(), clock.tick()).serialize();
+        let Ok(Message::Pong(.., pong_tick)) = Message::deserialize(&pong) else {
+            panic!("PONG did not decode");
+        };
+
+        let updates = room.client_updates(&HashMap::new());
+        assert_eq!(updates[0].1.snapshot.tick, pong_tick);
+    }
+}
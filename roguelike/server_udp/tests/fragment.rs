@@ -0,0 +1,254 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use server_udp::{
+    config::settings::FragmentSettings,
+    network::fragment::{self, FRAGMENT_HEADER_SIZE, FragmentError, Reassembler},
+};
+
+const MTU: usize = 64;
+
+fn client(port: u16) -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], port))
+}
+
+fn message(len: usize) -> Vec<u8> {
+    (0..len).map(|i| i as u8).collect()
+}
+
+fn fragments(message: &[u8], id: u16) -> Vec<Vec<u8>> {
+    let fragments = fragment::split(message, MTU, id).unwrap();
+    assert!(fragments.len() > 1, "sample should need fragmenting");
+    fragments
+}
+
+/// Feed every fragment, returning what the last one completed
+fn receive_all(
+    reassembler: &mut Reassembler,
+    sender: SocketAddr,
+    fragments: &[Vec<u8>],
+    now: Instant,
+) -> Option<Vec<u8>> {
+    let mut complete = None;
+    for fragment in fragments {
+        complete = reassembler.receive(sender, fragment, now).unwrap();
+    }
+    complete
+}
+
+#[test]
+fn small_messages_are_not_split() {
+    let small = message(MTU);
+    assert_eq!(fragment::split(&small, MTU, 1).unwrap(), [small]);
+}
+
+#[test]
+fn fragments_reassemble_in_order() {
+    let sent = message(200);
+    let mut reassembler = Reassembler::new(FragmentSettings::default());
+
+    let received = receive_all(
+        &mut reassembler,
+        client(1),
+        &fragments(&sent, 7),
+        Instant::now(),
+    );
+    assert_eq!(received, Some(sent));
+    assert_eq!(reassembler.pending_bytes(), 0);
+}
+
+#[test]
+fn fragments_reassemble_out_of_order() {
+    let sent = message(200);
+    let mut shuffled = fragments(&sent, 7);
+    shuffled.reverse();
+    shuffled.swap(0, 1);
+    let mut reassembler = Reassembler::new(FragmentSettings::default());
+
+    let received = receive_all(&mut reassembler, client(1), &shuffled, Instant::now());
+    assert_eq!(received, Some(sent));
+}
+
+#[test]
+fn resent_fragments_are_ignored() {
+    let sent = message(200);
+    let fragments = fragments(&sent, 7);
+    let mut reassembler = Reassembler::new(FragmentSettings::default());
+    let now = Instant::now();
+
+    assert_eq!(reassembler.receive(client(1), &fragments[0], now), Ok(None));
+    let held = reassembler.pending_bytes();
+    assert_eq!(reassembler.receive(client(1), &fragments[0], now), Ok(None));
+    assert_eq!(reassembler.pending_bytes(), held);
+
+    let received = receive_all(&mut reassembler, client(1), &fragments[1..], now);
+    assert_eq!(received, Some(sent));
+}
+
+#[test]
+fn senders_and_message_ids_are_kept_apart() {
+    let first = message(150);
+    let second: Vec<u8> = message(150).into_iter().rev().collect();
+    let first_fragments = fragments(&first, 1);
+    let second_fragments = fragments(&second, 1);
+    let mut reassembler = Reassembler::new(FragmentSettings::default());
+    let now = Instant::now();
+
+    for (a, b) in first_fragments.iter().zip(second_fragments.iter()).skip(1) {
+        assert_eq!(reassembler.receive(client(1), a, now), Ok(None));
+        assert_eq!(reassembler.receive(client(2), b, now), Ok(None));
+    }
+    assert_eq!(
+        reassembler.receive(client(2), &second_fragments[0], now),
+        Ok(Some(second))
+    );
+    assert_eq!(
+        reassembler.receive(client(1), &first_fragments[0], now),
+        Ok(Some(first))
+    );
+}
+
+#[test]
+fn disagreeing_counts_are_rejected() {
+    let fragments = fragments(&message(200), 7);
+    let mut reassembler = Reassembler::new(FragmentSettings::default());
+    let now = Instant::now();
+    reassembler.receive(client(1), &fragments[0], now).unwrap();
+
+    let mut other_count = fragments[1].clone();
+    other_count[4] += 1;
+    assert_eq!(
+        reassembler.receive(client(1), &other_count, now),
+        Err(FragmentError::Inconsistent(7))
+    );
+    assert_eq!(reassembler.pending_bytes(), 0);
+}
+
+#[test]
+fn malformed_fragments_are_rejected() {
+    let mut reassembler = Reassembler::new(FragmentSettings::default());
+    let now = Instant::now();
+
+    let header_only = fragments(&message(200), 7)[0][..FRAGMENT_HEADER_SIZE].to_vec();
+    assert_eq!(
+        reassembler.receive(client(1), &header_only, now),
+        Err(FragmentError::Malformed)
+    );
+
+    let mut index_past_count = fragments(&message(200), 7)[0].clone();
+    index_past_count[3] = index_past_count[4];
+    assert_eq!(
+        reassembler.receive(client(1), &index_past_count, now),
+        Err(FragmentError::Malformed)
+    );
+}
+
+#[test]
+fn expire_drops_sets_past_the_timeout() {
+    let settings = FragmentSettings::default();
+    let fragments = fragments(&message(200), 7);
+    let mut reassembler = Reassembler::new(settings);
+    let start = Instant::now();
+    reassembler
+        .receive(client(1), &fragments[0], start)
+        .unwrap();
+
+    reassembler.expire(start + settings.timeout);
+    assert!(reassembler.pending_bytes() > 0);
+
+    reassembler.expire(start + settings.timeout + Duration::from_millis(1));
+    assert_eq!(reassembler.pending_bytes(), 0);
+}
+
+#[test]
+fn timed_out_set_starts_over_before_it_is_expired() {
+    let settings = FragmentSettings::default();
+    let sent = message(200);
+    let fragments = fragments(&sent, 7);
+    let mut reassembler = Reassembler::new(settings);
+    let start = Instant::now();
+    let late = start + settings.timeout + Duration::from_millis(1);
+
+    receive_all(&mut reassembler, client(1), &fragments[1..], start);
+    assert_eq!(
+        reassembler.receive(client(1), &fragments[0], late),
+        Ok(None)
+    );
+
+    // Only the late fragment is left, the rest completes it again
+    assert_eq!(
+        receive_all(&mut reassembler, client(1), &fragments[1..], late),
+        Some(sent)
+    );
+}
+
+#[test]
+fn oversized_messages_are_rejected() {
+    let settings = FragmentSettings {
+        max_message_size: 100,
+        ..FragmentSettings::default()
+    };
+    let fragments = fragments(&message(200), 7);
+    let mut reassembler = Reassembler::new(settings);
+    let now = Instant::now();
+
+    let result = fragments
+        .iter()
+        .map(|fragment| reassembler.receive(client(1), fragment, now))
+        .find(Result::is_err);
+    assert!(matches!(result, Some(Err(FragmentError::TooLarge(_)))));
+    assert_eq!(reassembler.pending_bytes(), 0);
+}
+
+#[test]
+fn pending_bytes_are_limited_per_client() {
+    let settings = FragmentSettings {
+        max_message_size: 200,
+        max_pending_bytes_per_client: 200,
+        ..FragmentSettings::default()
+    };
+    let mut reassembler = Reassembler::new(settings);
+    let now = Instant::now();
+
+    // Two unfinished messages from one client go over its share
+    let first = fragments(&message(200), 1);
+    let second = fragments(&message(200), 2);
+    receive_all(&mut reassembler, client(1), &first[1..], now);
+    let result = second
+        .iter()
+        .skip(1)
+        .map(|fragment| reassembler.receive(client(1), fragment, now))
+        .find(Result::is_err);
+    assert_eq!(result, Some(Err(FragmentError::MemoryLimit)));
+
+    // Someone else still gets through
+    let other = message(200);
+    let received = receive_all(&mut reassembler, client(2), &fragments(&other, 1), now);
+    assert_eq!(received, Some(other));
+}
+
+#[test]
+fn pending_bytes_are_limited_in_total() {
+    let settings = FragmentSettings {
+        max_message_size: 200,
+        max_pending_bytes_per_client: 200,
+        max_pending_bytes: 250,
+        ..FragmentSettings::default()
+    };
+    let mut reassembler = Reassembler::new(settings);
+    let now = Instant::now();
+
+    let first = fragments(&message(200), 1);
+    receive_all(&mut reassembler, client(1), &first[1..], now);
+    let result = fragments(&message(200), 1)
+        .iter()
+        .skip(1)
+        .map(|fragment| reassembler.receive(client(2), fragment, now))
+        .find(Result::is_err);
+    assert_eq!(result, Some(Err(FragmentError::MemoryLimit)));
+
+    reassembler.forget(client(1));
+    assert_eq!(reassembler.pending_bytes(), 0);
+}
@@ -0,0 +1,134 @@
+use std::time::Instant;
+
+use server_udp::{
+    config::globals,
+    game::input::{InputAction, InputBuffer, InputCommand},
+};
+
+fn commands(seqs: &[u32]) -> Vec<InputCommand> {
+    seqs.iter()
+        .map(|&seq| InputCommand {
+            seq,
+            action: InputAction::Move(1.0, 0.0),
+        })
+        .collect()
+}
+
+/// Run ticks until the buffer hands something out, or give up
+fn next_seqs(buffer: &mut InputBuffer) -> Vec<u32> {
+    for _ in 0..=globals::INPUT_GAP_TOLERANCE_TICKS {
+        let ready = buffer.next_for_tick();
+        if !ready.is_empty() {
+            return ready.iter().map(|(command, _)| command.seq).collect();
+        }
+    }
+    Vec::new()
+}
+
+#[test]
+fn applies_one_input_per_tick_in_order() {
+    let mut buffer = InputBuffer::default();
+    buffer.receive(commands(&[1, 2, 3]), Instant::now());
+
+    assert_eq!(next_seqs(&mut buffer), [1]);
+    assert_eq!(next_seqs(&mut buffer), [2]);
+    assert_eq!(next_seqs(&mut buffer), [3]);
+    assert_eq!(buffer.stats().applied, 3);
+}
+
+#[test]
+fn first_input_sets_the_starting_point() {
+    let mut buffer = InputBuffer::default();
+    buffer.receive(commands(&[5000]), Instant::now());
+
+    assert_eq!(buffer.next_for_tick().len(), 1);
+    assert_eq!(buffer.last_applied(), 5000);
+    assert_eq!(buffer.stats().dropped, 0);
+}
+
+#[test]
+fn redundant_copies_are_counted_once() {
+    let mut buffer = InputBuffer::default();
+    let now = Instant::now();
+    buffer.receive(commands(&[1, 2, 3]), now);
+    assert_eq!(next_seqs(&mut buffer), [1]);
+
+    // Each datagram repeats the inputs before it
+    buffer.receive(commands(&[1, 2, 3, 4]), now);
+    assert_eq!(next_seqs(&mut buffer), [2]);
+    assert_eq!(next_seqs(&mut buffer), [3]);
+    assert_eq!(next_seqs(&mut buffer), [4]);
+
+    let stats = buffer.stats();
+    assert_eq!(stats.applied, 4);
+    assert_eq!(stats.duplicates, 3);
+}
+
+#[test]
+fn gap_is_waited_on_then_skipped() {
+    let mut buffer = InputBuffer::default();
+    let now = Instant::now();
+    buffer.receive(commands(&[1]), now);
+    assert_eq!(next_seqs(&mut buffer), [1]);
+
+    buffer.receive(commands(&[4]), now);
+    for _ in 0..globals::INPUT_GAP_TOLERANCE_TICKS {
+        assert!(buffer.next_for_tick().is_empty());
+    }
+    assert_eq!(next_seqs(&mut buffer), [4]);
+    assert_eq!(buffer.stats().dropped, 2);
+
+    // A skipped input showing up afterwards is late, not a duplicate
+    buffer.receive(commands(&[2]), now);
+    assert_eq!(buffer.stats().late, 1);
+    assert_eq!(buffer.stats().duplicates, 0);
+    assert!(buffer.next_for_tick().is_empty());
+}
+
+#[test]
+fn sequence_numbers_wrap_around() {
+    let mut buffer = InputBuffer::default();
+    buffer.receive(commands(&[u32::MAX - 1, u32::MAX, 0, 1]), Instant::now());
+
+    let mut applied = Vec::new();
+    while applied.len() < 4 {
+        let next = next_seqs(&mut buffer);
+        assert!(!next.is_empty(), "stalled after {applied:?}");
+        applied.extend(next);
+    }
+
+    assert_eq!(applied, [u32::MAX - 1, u32::MAX, 0, 1]);
+    assert_eq!(buffer.stats().dropped, 0);
+}
+
+#[test]
+fn inputs_far_ahead_are_rejected() {
+    let mut buffer = InputBuffer::default();
+    let now = Instant::now();
+    buffer.receive(commands(&[1]), now);
+    assert_eq!(next_seqs(&mut buffer), [1]);
+
+    buffer.receive(commands(&[1 + (u32::MAX / 2 - 1)]), now);
+    assert_eq!(buffer.stats().rejected, 1);
+    assert!(next_seqs(&mut buffer).is_empty());
+    assert_eq!(buffer.last_applied(), 1);
+}
+
+#[test]
+fn long_gap_is_skipped_at_once() {
+    let mut buffer = InputBuffer::default();
+    let now = Instant::now();
+    buffer.receive(commands(&[1]), now);
+    assert_eq!(next_seqs(&mut buffer), [1]);
+
+    let far = 1 + globals::MAX_INPUT_LEAD;
+    buffer.receive(commands(&[far]), now);
+    assert_eq!(next_seqs(&mut buffer), [far]);
+    assert_eq!(buffer.stats().dropped, globals::MAX_INPUT_LEAD - 1);
+
+    // Only the newest skipped inputs are remembered as late
+    buffer.receive(commands(&[far - 1, 2]), now);
+    let stats = buffer.stats();
+    assert_eq!(stats.late, 1);
+    assert_eq!(stats.duplicates, 1);
+}
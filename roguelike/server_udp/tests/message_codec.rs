@@ -0,0 +1,465 @@
+use proptest::{collection::vec, prelude::*};
+use server_udp::{
+    config::globals,
+    game::{
+        Position,
+        entity::{Entity, EntityKind},
+        input::{InputAction, InputCommand},
+        interest::ActorId,
+        inventory::{INVENTORY_SLOTS, Inventory, ItemStack},
+        mode::GameMode,
+        player::{LeaveReason, LifeState},
+        room::RunOutcome,
+        snapshot::{EnemySnapshot, PlayerSnapshot, RoomSnapshot},
+    },
+    network::{
+        compress::{Compression, CompressionOffer},
+        message::Message,
+    },
+};
+
+// Half a quantization step for each bit-packed field, plus a little float slack
+const POSITION_TOLERANCE: f32 = 0.01;
+const VELOCITY_TOLERANCE: f32 = 0.03;
+const TIMER_TOLERANCE: f32 = 0.035;
+const MOVE_TOLERANCE: f32 = 0.02;
+const AIM_TOLERANCE: f32 = 0.002;
+
+const WORLD_SIZE: f32 = 1024.0;
+
+fn finite() -> impl Strategy<Value = f32> + Clone {
+    -1.0e6f32..1.0e6
+}
+
+fn position() -> impl Strategy<Value = Position> {
+    (finite(), finite()).prop_map(|(x, y)| Position::new(x, y))
+}
+
+fn world_position() -> impl Strategy<Value = Position> {
+    (0.0..WORLD_SIZE, 0.0..WORLD_SIZE).prop_map(|(x, y)| Position::new(x, y))
+}
+
+fn item_stack() -> impl Strategy<Value = ItemStack> {
+    // Item 0 marks an empty inventory slot on the wire
+    (1..=u16::MAX, any::<u16>()).prop_map(|(item, count)| ItemStack::new(item, count))
+}
+
+fn inventory() -> impl Strategy<Value = Inventory> {
+    (
+        proptest::option::of(0..INVENTORY_SLOTS as u8),
+        vec(proptest::option::of(item_stack()), INVENTORY_SLOTS),
+    )
+        .prop_map(|(equipped, slots)| {
+            let mut inventory = Inventory {
+                equipped,
+                ..Default::default()
+            };
+            inventory.slots.copy_from_slice(&slots);
+            inventory
+        })
+}
+
+fn entity() -> impl Strategy<Value = Entity> {
+    let kind = prop_oneof![
+        item_stack().prop_map(EntityKind::Pickup),
+        (any::<bool>(), any::<bool>())
+            .prop_map(|(opened, locked)| EntityKind::Chest { opened, locked }),
+        Just(EntityKind::Stairs),
+    ];
+
+    (any::<u32>(), kind, position()).prop_map(|(id, kind, position)| Entity { id, kind, position })
+}
+
+fn life_state(timer: impl Strategy<Value = f32> + Clone) -> impl Strategy<Value = LifeState> {
+    prop_oneof![
+        Just(LifeState::Alive),
+        (timer.clone(), timer).prop_map(|(bleed_out, revive_progress)| LifeState::Downed {
+            bleed_out,
+            revive_progress,
+        }),
+        Just(LifeState::Dead),
+    ]
+}
+
+fn actor() -> impl Strategy<Value = ActorId> {
+    prop_oneof![
+        any::<u32>().prop_map(ActorId::Player),
+        any::<u32>().prop_map(ActorId::Enemy),
+    ]
+}
+
+fn game_mode() -> impl Strategy<Value = GameMode> {
+    prop_oneof![
+        Just(GameMode::Standard),
+        Just(GameMode::Hardcore),
+        Just(GameMode::Casual),
+    ]
+}
+
+fn leave_reason() -> impl Strategy<Value = LeaveReason> {
+    prop_oneof![
+        Just(LeaveReason::Quit),
+        Just(LeaveReason::Timeout),
+        Just(LeaveReason::Kicked),
+    ]
+}
+
+fn run_outcome() -> impl Strategy<Value = RunOutcome> {
+    prop_oneof![Just(RunOutcome::Defeat), Just(RunOutcome::Interrupted)]
+}
+
+fn compression() -> impl Strategy<Value = Compression> {
+    prop_oneof![
+        Just(Compression::None),
+        Just(Compression::Lz4),
+        Just(Compression::Zstd),
+        Just(Compression::ZstdDictionary),
+    ]
+}
+
+fn input_command() -> impl Strategy<Value = InputCommand> {
+    let action = prop_oneof![
+        (0.0f32..std::f32::consts::TAU, 0.0f32..=1.0).prop_map(
+            |(angle, length)| InputAction::Move(angle.cos() * length, angle.sin() * length)
+        ),
+        // Clients aim with a direction, pointing anywhere
+        (-1.0f32..=1.0, -1.0f32..=1.0).prop_map(|(x, y)| InputAction::Shoot(x, y)),
+    ];
+
+    (any::<u32>(), action).prop_map(|(seq, action)| InputCommand { seq, action })
+}
+
+fn input_commands() -> impl Strategy<Value = Vec<InputCommand>> {
+    // Mostly consecutive, like real redundant batches, with the odd jump
+    (
+        any::<u32>(),
+        vec(
+            (input_command(), any::<bool>()),
+            0..=globals::MAX_INPUTS_PER_PACKET,
+        ),
+    )
+        .prop_map(|(first, commands)| {
+            let mut seq = first;
+            commands
+                .into_iter()
+                .map(|(mut command, consecutive)| {
+                    seq = if consecutive {
+                        seq.wrapping_add(1)
+                    } else {
+                        command.seq
+                    };
+                    command.seq = seq;
+                    command
+                })
+                .collect()
+        })
+}
+
+fn room_snapshot() -> impl Strategy<Value = RoomSnapshot> {
+    let player = (
+        any::<u32>(),
+        world_position(),
+        (-120.0f32..120.0, -120.0f32..120.0),
+        any::<i32>(),
+        any::<u16>(),
+        life_state(0.0f32..64.0),
+    )
+        .prop_map(
+            |(id, position, (vx, vy), health, ping_ms, life)| PlayerSnapshot {
+                id,
+                position,
+                velocity: Position::new(vx, vy),
+                health,
+                ping_ms,
+                life,
+            },
+        );
+    let enemy =
+        (any::<u32>(), world_position(), any::<i32>()).prop_map(|(id, position, health)| {
+            EnemySnapshot {
+                id,
+                position,
+                health,
+            }
+        });
+
+    (
+        any::<u32>(),
+        any::<u32>(),
+        world_position(),
+        vec(player, 0..8),
+        vec(enemy, 0..32),
+    )
+        .prop_map(
+            |(tick, ack_input_seq, ack_position, players, enemies)| RoomSnapshot {
+                tick,
+                ack_input_seq,
+                ack_position,
+                players,
+                enemies,
+            },
+        )
+}
+
+/// Every variant whose fields go over the wire unchanged
+fn exact_message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        ".*".prop_map(Message::Error),
+        (any::<u32>(), any::<u64>()).prop_map(|(seq, time)| Message::Ping(seq, time)),
+        (any::<u32>(), any::<u64>(), any::<u64>(), any::<u32>())
+            .prop_map(|(seq, echo, time, tick)| Message::Pong(seq, echo, time, tick)),
+        (".*", any::<u8>(), any::<u32>()).prop_map(|(name, algorithms, dictionary_id)| {
+            Message::Handshake(
+                name,
+                CompressionOffer {
+                    algorithms,
+                    dictionary_id,
+                },
+            )
+        }),
+        (any::<u32>(), compression()).prop_map(|(id, compression)| Message::Ack(id, compression)),
+        (".*", ".*", game_mode())
+            .prop_map(|(name, pass, mode)| Message::CreateRoom(name, pass, mode)),
+        (any::<u32>(), leave_reason()).prop_map(|(id, reason)| Message::Leave(id, reason)),
+        (any::<u32>(), ".*").prop_map(|(room, pass)| Message::JoinRoom(room, pass)),
+        any::<u8>().prop_map(Message::UseItem),
+        (any::<u8>(), any::<u16>()).prop_map(|(slot, count)| Message::DropItem(slot, count)),
+        any::<u8>().prop_map(Message::Equip),
+        any::<u32>().prop_map(Message::Interact),
+        inventory().prop_map(Message::Inventory),
+        entity().prop_map(Message::EntitySpawn),
+        any::<u32>().prop_map(Message::EntityDespawn),
+        (any::<u32>(), any::<u64>(), any::<u64>(), any::<u32>()).prop_map(
+            |(depth, seed, elapsed, kills)| Message::FloorChanged(depth, seed, elapsed, kills)
+        ),
+        Just(Message::VoteDescend),
+        (any::<u32>(), life_state(finite()), any::<i32>())
+            .prop_map(|(id, life, health)| Message::PlayerState(id, life, health)),
+        (run_outcome(), any::<u32>(), any::<u64>(), any::<u32>()).prop_map(
+            |(outcome, depth, elapsed, kills)| Message::RunEnded(outcome, depth, elapsed, kills)
+        ),
+        actor().prop_map(Message::ActorSpawn),
+        actor().prop_map(Message::ActorDespawn),
+        any::<u32>().prop_map(Message::SnapshotAck),
+        (".*", any::<u16>()).prop_map(|(reason, secs)| Message::ServerShutdown(reason, secs)),
+        ".*".prop_map(Message::Motd),
+    ]
+}
+
+/// Every variant, including the bit-packed ones that lose some float precision
+fn any_message() -> impl Strategy<Value = Message> {
+    prop_oneof![
+        3 => exact_message(),
+        1 => (any::<u32>(), input_commands())
+            .prop_map(|(id, commands)| Message::PlayerInput(id, commands)),
+        1 => room_snapshot().prop_map(Message::RoomSnapshot),
+    ]
+}
+
+fn close(a: f32, b: f32, tolerance: f32) -> bool {
+    (a - b).abs() <= tolerance
+}
+
+fn close_position(a: Position, b: Position, tolerance: f32) -> bool {
+    close(a.x, b.x, tolerance) && close(a.y, b.y, tolerance)
+}
+
+fn close_life(a: LifeState, b: LifeState) -> bool {
+    match (a, b) {
+        (
+            LifeState::Downed {
+                bleed_out: a_bleed,
+                revive_progress: a_revive,
+            },
+            LifeState::Downed {
+                bleed_out: b_bleed,
+                revive_progress: b_revive,
+            },
+        ) => close(a_bleed, b_bleed, TIMER_TOLERANCE) && close(a_revive, b_revive, TIMER_TOLERANCE),
+        (a, b) => a == b,
+    }
+}
+
+/// Only a trailing optional field may be missing, and a bare PING is the legacy pong
+fn legacy_prefix(message: &Message, length: usize, full_length: usize) -> bool {
+    match message {
+        Message::CreateRoom(..) | Message::Leave(..) | Message::Ack(..) => {
+            length == full_length - 1
+        }
+        // Algorithm bits and dictionary id
+        Message::Handshake(..) => length == full_length - 5,
+        Message::Ping(..) => length == 1,
+        _ => false,
+    }
+}
+
+proptest! {
+    #[test]
+    fn exact_messages_round_trip(message in exact_message()) {
+        let packet = message.serialize();
+        prop_assert_eq!(Message::deserialize(&packet).unwrap(), message);
+    }
+
+    #[test]
+    fn snapshots_round_trip_within_precision(snapshot in room_snapshot()) {
+        let packet = Message::RoomSnapshot(snapshot.clone()).serialize();
+        let Message::RoomSnapshot(decoded) = Message::deserialize(&packet).unwrap() else {
+            panic!("decoded into another variant");
+        };
+
+        prop_assert_eq!(decoded.tick, snapshot.tick);
+        prop_assert_eq!(decoded.ack_input_seq, snapshot.ack_input_seq);
+        prop_assert!(close_position(decoded.ack_position, snapshot.ack_position, POSITION_TOLERANCE));
+        prop_assert_eq!(decoded.players.len(), snapshot.players.len());
+        prop_assert_eq!(decoded.enemies.len(), snapshot.enemies.len());
+
+        for (got, sent) in decoded.players.iter().zip(snapshot.players.iter()) {
+            prop_assert_eq!(got.id, sent.id);
+            prop_assert_eq!(got.health, sent.health);
+            prop_assert_eq!(got.ping_ms, sent.ping_ms);
+            prop_assert!(close_position(got.position, sent.position, POSITION_TOLERANCE));
+            prop_assert!(close_position(got.velocity, sent.velocity, VELOCITY_TOLERANCE));
+            prop_assert!(close_life(got.life, sent.life));
+        }
+
+        for (got, sent) in decoded.enemies.iter().zip(snapshot.enemies.iter()) {
+            prop_assert_eq!(got.id, sent.id);
+            prop_assert_eq!(got.health, sent.health);
+            prop_assert!(close_position(got.position, sent.position, POSITION_TOLERANCE));
+        }
+    }
+
+    #[test]
+    fn inputs_round_trip_within_precision(player_id: u32, commands in input_commands()) {
+        let packet = Message::PlayerInput(player_id, commands.clone()).serialize();
+        let Message::PlayerInput(decoded_id, decoded) = Message::deserialize(&packet).unwrap() else {
+            panic!("decoded into another variant");
+        };
+
+        prop_assert_eq!(decoded_id, player_id);
+        prop_assert_eq!(decoded.len(), commands.len());
+
+        for (got, sent) in decoded.iter().zip(commands.iter()) {
+            prop_assert_eq!(got.seq, sent.seq);
+            match (got.action, sent.action) {
+                (InputAction::Move(gx, gy), InputAction::Move(sx, sy)) => {
+                    prop_assert!(close(gx, sx, MOVE_TOLERANCE) && close(gy, sy, MOVE_TOLERANCE));
+                }
+                // Only the direction goes over the wire
+                (InputAction::Shoot(gx, gy), InputAction::Shoot(sx, sy)) => {
+                    let length = (sx * sx + sy * sy).sqrt();
+                    let (sx, sy) = if length > 0.0 { (sx / length, sy / length) } else { (0.0, 0.0) };
+                    prop_assert!(close(gx, sx, AIM_TOLERANCE) && close(gy, sy, AIM_TOLERANCE));
+                }
+                (got, sent) => prop_assert!(false, "{:?} decoded as {:?}", sent, got),
+            }
+        }
+    }
+
+    /// Once on the wire, a message encodes to the same bytes every time
+    #[test]
+    fn reencoding_is_stable(message in any_message()) {
+        let packet = message.serialize();
+        let decoded = Message::deserialize(&packet).unwrap();
+        prop_assert_eq!(decoded.serialize(), packet);
+    }
+
+    #[test]
+    fn truncated_packets_are_rejected(message in any_message(), cut in any::<prop::sample::Index>()) {
+        let packet = message.serialize();
+        let length = cut.index(packet.len());
+
+        if !legacy_prefix(&message, length, packet.len()) {
+            prop_assert!(Message::deserialize(&packet[..length]).is_err());
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected(message in any_message(), extra in vec(any::<u8>(), 1..8)) {
+        let mut packet = message.serialize();
+        packet.extend_from_slice(&extra);
+        prop_assert!(Message::deserialize(&packet).is_err());
+    }
+
+    #[test]
+    fn random_bytes_never_panic(packet in vec(any::<u8>(), 0..512)) {
+        let _ = Message::deserialize(&packet);
+    }
+
+    /// Valid command byte, garbage body: the paths most likely to index out of bounds
+    #[test]
+    fn garbage_after_a_command_never_panics(command in 0u8..32, body in vec(any::<u8>(), 0..64)) {
+        let mut packet = vec![command];
+        packet.extend_from_slice(&body);
+        let _ = Message::deserialize(&packet);
+    }
+}
+
+#[test]
+fn short_ack_is_an_error() {
+    assert!(Message::deserialize(&[globals::commands::ACK, 1]).is_err());
+}
+
+#[test]
+fn create_room_password_past_the_end_is_an_error() {
+    // Claims a 200 byte password with none following
+    let packet = [globals::commands::CREATE_ROOM, 1, 0, b'r', 200, 0];
+    assert!(Message::deserialize(&packet).is_err());
+}
+
+#[test]
+fn create_room_without_mode_defaults_to_standard() {
+    let packet = [globals::commands::CREATE_ROOM, 1, 0, b'r', 0, 0];
+    assert_eq!(
+        Message::deserialize(&packet).unwrap(),
+        Message::CreateRoom("r".to_string(), String::new(), GameMode::Standard)
+    );
+}
+
+#[test]
+fn bare_ping_reads_as_legacy_pong() {
+    assert_eq!(
+        Message::deserialize(&[globals::commands::PING]).unwrap(),
+        Message::Ping(0, 0)
+    );
+}
+
+#[test]
+fn shots_keep_negative_directions() {
+    let directions = [
+        (-1.0, 0.0),
+        (0.0, -1.0),
+        (-3.0, -4.0),
+        (0.5, -0.5),
+        (0.0, 0.0),
+    ];
+    let commands: Vec<InputCommand> = directions
+        .iter()
+        .enumerate()
+        .map(|(seq, &(x, y))| InputCommand {
+            seq: seq as u32,
+            action: InputAction::Shoot(x, y),
+        })
+        .collect();
+
+    let packet = Message::PlayerInput(1, commands).serialize();
+    let Message::PlayerInput(_, decoded) = Message::deserialize(&packet).unwrap() else {
+        panic!("decoded into another variant");
+    };
+
+    for (command, (x, y)) in decoded.iter().zip(directions) {
+        let InputAction::Shoot(gx, gy) = command.action else {
+            panic!("{:?} is not a shot", command.action);
+        };
+        let length = f32::hypot(x, y).max(f32::MIN_POSITIVE);
+        assert!(
+            close(gx, x / length, AIM_TOLERANCE) && close(gy, y / length, AIM_TOLERANCE),
+            "Shoot({x}, {y}) decoded as Shoot({gx}, {gy})"
+        );
+    }
+}
+
+#[test]
+fn empty_packet_is_an_error() {
+    assert!(Message::deserialize(&[]).is_err());
+}